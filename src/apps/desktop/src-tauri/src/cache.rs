@@ -0,0 +1,37 @@
+// Caches model metadata fetched from the Ollama API
+
+use crate::ollama_api::ModelInfo;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default seconds the installed model list is trusted before it is fetched again.
+pub const DEFAULT_MODEL_LIST_TTL_SECS: u64 = 30;
+
+static MODEL_LIST_TTL_SECS: AtomicU64 = AtomicU64::new(DEFAULT_MODEL_LIST_TTL_SECS);
+
+/// Sets how long the model list is cached, falling back to the default for `None`; zero disables caching.
+pub fn set_model_list_ttl(secs: Option<u64>) {
+    MODEL_LIST_TTL_SECS.store(secs.unwrap_or(DEFAULT_MODEL_LIST_TTL_SECS), Ordering::Relaxed);
+}
+
+#[derive(Default)]
+pub struct ModelCache {
+    /// Capabilities reported by `/api/show`, keyed by model name.
+    pub capabilities: HashMap<String, Vec<String>>,
+    /// Details reported by `/api/show`, keyed by model name.
+    pub info: HashMap<String, ModelInfo>,
+    /// Installed model names from `/api/tags` and when they were fetched.
+    pub models: Option<(Instant, Vec<String>)>,
+}
+
+impl ModelCache {
+    /// Returns the cached model list unless it has outlived the configured TTL.
+    pub fn fresh_models(&self) -> Option<&Vec<String>> {
+        let ttl = Duration::from_secs(MODEL_LIST_TTL_SECS.load(Ordering::Relaxed));
+        self.models
+            .as_ref()
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < ttl)
+            .map(|(_, models)| models)
+    }
+}
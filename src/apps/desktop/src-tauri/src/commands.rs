@@ -1,24 +1,139 @@
 // Handles Tauri command definitions
 
+use crate::cache::ModelCache;
 use crate::db;
+use crate::embeddings;
+use crate::export;
+use crate::importer;
 use crate::ollama_api;
-use crate::session::GenerationState;
+use crate::settings::{self, Settings, SettingsUpdate};
+use crate::session::{GenerationState, GenerationStateSnapshot, PullState, RequestLimiter};
+use crate::shortcuts;
+use crate::window_state;
 use std::sync::Arc;
-use tauri::{command, State};
+use tauri::{command, ClipboardManager, State};
 use tokio::sync::Mutex;
 use serde_json::Value;
 use rusqlite::Connection;
 
 
+// List installed models, served from a short-lived cache; `force` fetches a fresh list
 #[command]
-pub async fn load_models() -> Result<Vec<String>, String> {
-    ollama_api::fetch_models().await
+pub async fn load_models(
+    force: Option<bool>,
+    cache: State<'_, Arc<Mutex<ModelCache>>>,
+) -> Result<Vec<String>, String> {
+    ollama_api::fetch_models_cached(cache.inner(), force.unwrap_or(false)).await
+}
+
+// Installed models ordered by when they were last used in the app
+#[command]
+pub async fn fetch_models_by_recent_use(
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<String>, String> {
+    ollama_api::fetch_models_by_recent_use(db_conn.inner()).await
+}
+
+// Suggest a default model for first run; `None` when no models are installed
+#[command]
+pub async fn suggest_default_model() -> Result<Option<String>, String> {
+    let models = ollama_api::fetch_models_detailed().await?;
+    Ok(ollama_api::pick_default_model(&models))
+}
+
+// List installed variants (quantizations, sizes) of a model family, grouped by base model
+#[command]
+pub async fn list_model_variants(family: Option<String>) -> Result<Vec<ollama_api::ModelVariantGroup>, String> {
+    let models = ollama_api::fetch_models_detailed().await?;
+    Ok(ollama_api::group_model_variants(models, family.as_deref()))
+}
+
+// Check which requested features (vision, tools, embeddings) a model supports
+#[command]
+pub async fn check_model_supports(
+    model: String,
+    features: Vec<String>,
+    cache: State<'_, Arc<Mutex<ModelCache>>>,
+) -> Result<ollama_api::ModelSupport, String> {
+    ollama_api::check_model_supports(&model, &features, cache.inner()).await
+}
+
+// Get a model's parameters, template, details and context length; the Modelfile is
+// left out unless `include_modelfile` is set, since it can be large
+#[command]
+pub async fn get_model_info(
+    model_name: String,
+    include_modelfile: Option<bool>,
+    cache: State<'_, Arc<Mutex<ModelCache>>>,
+) -> Result<ollama_api::ModelInfo, String> {
+    ollama_api::get_model_info(&model_name, include_modelfile.unwrap_or(false), cache.inner()).await
+}
+
+// List the models Ollama has loaded in memory, with their VRAM use and unload time
+#[command]
+pub async fn list_running_models() -> Result<Vec<ollama_api::RunningModel>, String> {
+    ollama_api::fetch_running_models().await
+}
+
+// Unload a model from memory without waiting for its keep_alive to run out
+#[command]
+pub async fn unload_model(model_name: String) -> Result<(), String> {
+    ollama_api::unload_model(&model_name).await
+}
+
+// Download a model, reporting progress through `model-pull-progress` events
+#[command]
+pub async fn pull_model(
+    model_name: String,
+    window: tauri::Window,
+    pulls: State<'_, Arc<Mutex<PullState>>>,
+    cache: State<'_, Arc<Mutex<ModelCache>>>,
+) -> Result<(), String> {
+    ollama_api::pull_model(&model_name, &window, pulls.inner()).await?;
+    // A re-pulled tag may point to a model with different capabilities
+    let mut cache = cache.lock().await;
+    cache.capabilities.remove(&model_name);
+    cache.info.remove(&model_name);
+    cache.models = None;
+    Ok(())
+}
+
+// Cancel an in-progress model download; returns whether one was running
+#[command]
+pub async fn cancel_model_pull(
+    model_name: String,
+    pulls: State<'_, Arc<Mutex<PullState>>>,
+) -> Result<bool, String> {
+    Ok(pulls.lock().await.cancel(&model_name))
+}
+
+// Delete an installed model, clearing the selected model when it was the one removed
+#[command]
+pub async fn delete_model(
+    model_name: String,
+    conn: State<'_, db::DbPool>,
+    cache: State<'_, Arc<Mutex<ModelCache>>>,
+) -> Result<(), String> {
+    ollama_api::delete_model(&model_name).await?;
+    {
+        let mut cache = cache.lock().await;
+        cache.capabilities.remove(&model_name);
+        cache.info.remove(&model_name);
+        cache.models = None;
+    }
+
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    let selected = db::get_config_value(&conn, "selected_model_name").map_err(|e| e.to_string())?;
+    if selected.as_deref() == Some(model_name.as_str()) {
+        db::delete_config_value(&conn, "selected_model_name").map_err(|e| e.to_string())?;
+    }
+    Ok(())
 }
 
 // Get selected model
 #[command]
-pub async fn get_selected_model(conn: tauri::State<'_, Arc<Mutex<Connection>>>) -> Result<String, String> {
-    let conn = conn.lock().await;
+pub async fn get_selected_model(conn: tauri::State<'_, db::DbPool>) -> Result<String, String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
     db::get_config_value(&conn, "selected_model_name")
         .map(|model| model.unwrap_or_else(|| "".to_string()))
         .map_err(|e| e.to_string())
@@ -27,28 +142,120 @@ pub async fn get_selected_model(conn: tauri::State<'_, Arc<Mutex<Connection>>>)
 // Save selected model
 #[command]
 pub async fn save_selected_model(
-    conn: tauri::State<'_, Arc<Mutex<Connection>>>,
+    conn: tauri::State<'_, db::DbPool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
     model_name: String,
 ) -> Result<(), String> {
-    let conn = conn.lock().await;
-    db::update_config_value(&conn, "selected_model_name", &model_name)
-        .map_err(|e| e.to_string())?;
+    let cancel = {
+        let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+        db::update_config_value(&conn, "selected_model_name", &model_name)
+            .map_err(|e| e.to_string())?;
+        cancel_on_model_change(&conn)
+    };
+
+    if cancel {
+        let mut state = state.lock().await;
+        if let Some(session_id) = state.current_session_id {
+            abort_for_model_change(&mut state, session_id, &model_name);
+        }
+    }
     Ok(())
 }
 
+// Set the model of a session, without pinning its digest
+#[command]
+pub async fn set_session_model(
+    session_id: i64,
+    model: String,
+    conn: State<'_, db::DbPool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+) -> Result<(), String> {
+    let cancel = {
+        let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+        db::set_session_model(&conn, session_id, &model).map_err(|e| e.to_string())?;
+        cancel_on_model_change(&conn)
+    };
+
+    if cancel {
+        abort_for_model_change(&mut *state.lock().await, session_id, &model);
+    }
+    Ok(())
+}
+
+// Whether switching models should abort the in-flight generation (config `cancel_on_model_change`, default off)
+fn cancel_on_model_change(conn: &Connection) -> bool {
+    db::get_config_value(conn, "cancel_on_model_change")
+        .ok()
+        .flatten()
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+fn abort_for_model_change(state: &mut GenerationState, session_id: i64, model: &str) {
+    if state.abort_if_model_changed(session_id, model) {
+        println!("Aborted generation of session {} after switching to {}", session_id, model);
+    }
+}
+
+// Get the global default for showing reasoning (<think>) output
+#[command]
+pub async fn get_show_thinking(conn: State<'_, db::DbPool>) -> Result<bool, String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    db::get_config_value(&conn, "show_thinking")
+        .map(|value| value.map(|v| v == "true").unwrap_or(true))
+        .map_err(|e| e.to_string())
+}
+
+// Set the global default for showing reasoning (<think>) output
+#[command]
+pub async fn set_show_thinking(
+    conn: State<'_, db::DbPool>,
+    enabled: bool,
+) -> Result<(), String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    db::update_config_value(&conn, "show_thinking", &enabled.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// Override showing reasoning output for one session; `None` falls back to the global default
+#[command]
+pub async fn set_session_show_thinking(
+    session_id: i64,
+    enabled: Option<bool>,
+    conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    db::set_session_show_thinking(&conn, session_id, enabled).map_err(|e| e.to_string())
+}
+
 #[command]
 pub async fn delete_chat_session(
     session_id: i64,
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
+    db: State<'_, db::DbPool>,
     state: State<'_, Arc<Mutex<GenerationState>>>,
 ) -> Result<(), String> {
     let result = db::remove_chat_session(session_id, db, state).await;
     result.map_err(|e| format!("Failed to delete session: {}", e))
 }
 
+// Branch a conversation: copy a session's settings and its messages up to and including
+// `up_to_message_id` into a new session, returning the new session's id
+#[command]
+pub async fn fork_session(
+    session_id: i64,
+    up_to_message_id: i64,
+    db: State<'_, db::DbPool>,
+) -> Result<i64, String> {
+    let mut conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::fork_session(&mut conn, session_id, up_to_message_id).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => format!("Message {} is not in session {}", up_to_message_id, session_id),
+        e => format!("Failed to fork session: {}", e),
+    })
+}
+
 #[command]
 pub async fn get_current_session(
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
+    db: State<'_, db::DbPool>,
     state: State<'_, Arc<Mutex<GenerationState>>>,
 ) -> Result<db::CurrentSession, String> {
     db::fetch_current_session(db, state).await.map_err(|e| e.to_string())
@@ -58,87 +265,1354 @@ pub async fn get_current_session(
 pub async fn update_chat_session_name(
     session_id: i64,
     new_name: String,
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
+    db: State<'_, db::DbPool>,
 ) -> Result<(), String> {
     db::rename_chat_session(session_id, new_name, db).await.map_err(|e| e.to_string())
 }
 
+// Pin or unpin a session. Passing `pinned` sets that state, so repeating the call is harmless;
+// without it the current state is flipped. Returns whether the session is now pinned.
+#[command]
+pub async fn toggle_session_pin(
+    session_id: i64,
+    pinned: Option<bool>,
+    db: State<'_, db::DbPool>,
+) -> Result<bool, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::set_session_pinned(&conn, session_id, pinned).map_err(|e| e.to_string())
+}
+
+// Archive a session to hide it from the default session list, or restore it
+#[command]
+pub async fn set_session_archived(
+    session_id: i64,
+    archived: bool,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::set_session_archived(&conn, session_id, archived).map_err(|e| e.to_string())
+}
+
+// Count active and archived sessions, e.g. for a "12 archived" label
+#[command]
+pub async fn count_chat_sessions(
+    db: State<'_, db::DbPool>,
+) -> Result<db::SessionCounts, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::count_sessions(&conn).map_err(|e| e.to_string())
+}
+
+// Delete all chats for privacy, keeping settings unless `include_config` is set,
+// and start over with no current session
+#[command]
+pub async fn clear_all_data(
+    include_config: Option<bool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db: State<'_, db::DbPool>,
+) -> Result<db::ClearedData, String> {
+    let mut state = state.lock().await;
+    // A running reply would otherwise be saved into a deleted session
+    state.abort();
+    let mut conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    let include_config = include_config.unwrap_or(false);
+    let cleared = db::clear_all_data(&mut conn, include_config).map_err(|e| e.to_string())?;
+    if include_config {
+        // Fall back to the default Ollama URL and timeouts
+        settings::apply_runtime_config(&conn);
+    }
+    state.current_session_id = Some(-1);
+    Ok(cleared)
+}
+
+// Save a copy of the whole database to `dest_path`; safe while chats are in use
+#[command]
+pub async fn backup_database(
+    dest_path: String,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::backup_database(&conn, std::path::Path::new(&dest_path))
+}
+
+// Replace the database with a backup made by `backup_database`, returning the restored
+// session counts. The current database is kept as a `.bak` file beside it
+#[command]
+pub async fn restore_database(
+    src_path: String,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db: State<'_, db::DbPool>,
+) -> Result<db::SessionCounts, String> {
+    let mut state = state.lock().await;
+    // A running reply would otherwise be saved into the replaced database
+    state.abort();
+    let mut conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::restore_database(&mut conn, std::path::Path::new(&src_path))?;
+    state.current_session_id = Some(-1);
+    settings::apply_runtime_config(&conn);
+    db::count_sessions(&conn).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn create_folder(
+    name: String,
+    db: State<'_, db::DbPool>,
+) -> Result<i64, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::create_folder(&conn, name).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn rename_folder(
+    id: i64,
+    name: String,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::rename_folder(&conn, id, name).map_err(|e| e.to_string())
+}
+
+// Delete a folder; the sessions in it are kept and become unfiled
+#[command]
+pub async fn delete_folder(
+    id: i64,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let mut conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::delete_folder(&mut conn, id).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn load_folders(
+    db: State<'_, db::DbPool>,
+) -> Result<Vec<db::Folder>, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::fetch_folders(&conn).map_err(|e| e.to_string())
+}
+
+// Move a session into a folder, or out of its folder when `folder_id` is `None`
+#[command]
+pub async fn move_session_to_folder(
+    session_id: i64,
+    folder_id: Option<i64>,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::move_session_to_folder(&conn, session_id, folder_id).map_err(|e| e.to_string())
+}
+
 #[command]
 pub async fn load_chat_sessions(
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
+    include_archived: Option<bool>,
+    db: State<'_, db::DbPool>,
+) -> Result<Vec<db::ChatSession>, String> {
+    db::fetch_chat_sessions(include_archived.unwrap_or(false), db).await.map_err(|e| e.to_string())
+}
+
+// List sessions that contain messages generated by the given model
+#[command]
+pub async fn sessions_using_model(
+    model: String,
+    db: State<'_, db::DbPool>,
 ) -> Result<Vec<db::ChatSession>, String> {
-    db::fetch_chat_sessions(db).await.map_err(|e| e.to_string())
+    db::fetch_sessions_using_model(&model, db).await.map_err(|e| e.to_string())
+}
+
+// Read the audit log of session and model actions, newest first
+#[command]
+pub async fn fetch_audit_log(
+    limit: Option<i64>,
+    offset: Option<i64>,
+    action: Option<String>,
+    db: State<'_, db::DbPool>,
+) -> Result<Vec<db::AuditEntry>, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::fetch_audit_log(&conn, action.as_deref(), limit.unwrap_or(100).clamp(1, 1000), offset.unwrap_or(0).max(0))
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 pub async fn clear_current_session(
     state: tauri::State<'_, Arc<Mutex<GenerationState>>>,
+    db: State<'_, db::DbPool>,
 ) -> Result<(), String> {
     let mut gen_state = state.lock().await;
     gen_state.current_session_id = Some(-1);
-    Ok(())
+    drop(gen_state);
+
+    // The next launch starts on an empty chat rather than the session that was left
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::set_last_session(&conn, None).map_err(|e| e.to_string())
+}
+
+// Start a fresh, empty session and make it current, returning its id. It is titled
+// after the first exchange like a session created by sending a prompt
+#[command]
+pub async fn new_chat(
+    model: Option<String>,
+    system_prompt: Option<String>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db: State<'_, db::DbPool>,
+) -> Result<i64, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    let session_id = db::create_session(&conn, ollama_api::NEW_CHAT_TITLE).map_err(|e| e.to_string())?;
+    if let Some(model) = model.as_deref().map(str::trim).filter(|m| !m.is_empty()) {
+        db::set_session_model(&conn, session_id, model).map_err(|e| e.to_string())?;
+    }
+    if let Some(prompt) = system_prompt.as_deref().filter(|p| !p.trim().is_empty()) {
+        db::set_session_system_prompt(&conn, session_id, Some(prompt)).map_err(|e| e.to_string())?;
+    }
+
+    state.lock().await.current_session_id = Some(session_id);
+    db::set_last_session(&conn, Some(session_id)).map_err(|e| e.to_string())?;
+    Ok(session_id)
 }
 
 #[tauri::command]
 pub async fn set_current_session(
     session_id: i64,
     state: tauri::State<'_, Arc<Mutex<GenerationState>>>,
+    db: State<'_, db::DbPool>,
 ) -> Result<(), String> {
     let mut gen_state = state.lock().await;
     gen_state.current_session_id = Some(session_id);
-    Ok(())
+    drop(gen_state);
+
+    // Remembered so the next launch reopens this session
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::set_last_session(&conn, Some(session_id)).map_err(|e| e.to_string())
 }
 
 #[command]
+#[allow(clippy::too_many_arguments)]
 pub async fn generate_chat(
+    prompt: String,
+    model: Option<String>,
+    keep_alive: Option<String>,
+    no_history: Option<bool>,
+    options: Option<ollama_api::GenerationOptions>,
+    images: Option<Vec<String>>,
+    window: tauri::Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<String, ollama_api::GenerationError> {
+    let request = ollama_api::ChatRequest {
+        prompt,
+        model: model.unwrap_or_default(),
+        keep_alive,
+        no_history: no_history.unwrap_or(false),
+        options,
+        images: images.unwrap_or_default(),
+    };
+    ollama_api::process_chat_generation(request, window, state, db_conn, limiter).await
+}
+
+// Start a generation and return the new message ids immediately; the reply streams
+// into the assistant row via `chat-token` events and is finalized with `chat-done`
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn start_chat_generation(
+    prompt: String,
+    model: Option<String>,
+    keep_alive: Option<String>,
+    no_history: Option<bool>,
+    options: Option<ollama_api::GenerationOptions>,
+    images: Option<Vec<String>>,
+    window: tauri::Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<ollama_api::StartedGeneration, ollama_api::GenerationError> {
+    let request = ollama_api::ChatRequest {
+        prompt,
+        model: model.unwrap_or_default(),
+        keep_alive,
+        no_history: no_history.unwrap_or(false),
+        options,
+        images: images.unwrap_or_default(),
+    };
+    ollama_api::start_chat_generation(request, window, state, db_conn, limiter).await
+}
+
+// Create a session with the given title and generate a response in it in one call
+#[command]
+pub async fn quick_chat(
+    title: String,
+    prompt: String,
+    model: String,
+    window: tauri::Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<ollama_api::QuickChatResult, ollama_api::GenerationError> {
+    let request = ollama_api::ChatRequest {
+        prompt,
+        model,
+        ..Default::default()
+    };
+    ollama_api::quick_chat(title, request, window, state, db_conn, limiter).await
+}
+
+// Get the maximum number of concurrent Ollama requests
+#[command]
+pub async fn get_max_concurrency(
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<usize, String> {
+    Ok(limiter.lock().await.limit())
+}
+
+// Set the maximum number of concurrent Ollama requests; excess requests are queued
+#[command]
+pub async fn set_max_concurrency(
+    n: usize,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    if !(1..=16).contains(&n) {
+        return Err("Max concurrency must be between 1 and 16".to_string());
+    }
+
+    {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        db::update_config_value(&conn, "max_concurrency", &n.to_string()).map_err(|e| e.to_string())?;
+    }
+
+    limiter.lock().await.set_limit(n);
+    Ok(())
+}
+
+// Generate several candidate responses in parallel without saving them
+#[command]
+pub async fn generate_candidates(
     prompt: String,
     model: String,
+    n: usize,
+    options: Option<ollama_api::GenerationOptions>,
     state: State<'_, Arc<Mutex<GenerationState>>>,
-    db_conn: State<'_, Arc<Mutex<rusqlite::Connection>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<Vec<ollama_api::Candidate>, String> {
+    ollama_api::generate_candidates(prompt, model, n, options, state, db_conn, limiter).await
+}
+
+// Persist the candidate the user picked, together with its prompt, into the current session
+#[command]
+pub async fn accept_candidate(
+    prompt: String,
+    content: String,
+    model: String,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<i64, String> {
+    let mut gen_state = state.lock().await;
+
+    let session_id = match gen_state.current_session_id {
+        Some(id) if id != -1 => id,
+        _ => {
+            let title: String = prompt.chars().take(50).collect();
+            let session_id = db::get_or_create_session(db_conn.inner(), title.trim()).await?;
+            if let Err(e) = db::checkout(&db_conn).and_then(|conn| db::set_last_session(&conn, Some(session_id))) {
+                eprintln!("Failed to remember the last session: {}", e);
+            }
+            session_id
+        }
+    };
+    gen_state.current_session_id = Some(session_id);
+
+    db::save_chat_message(session_id, "user", &prompt, None, db_conn.clone())
+        .await
+        .map_err(|e| format!("Failed to save user message: {}", e))?;
+    db::save_chat_message(session_id, "assistant", &content, Some(&model), db_conn)
+        .await
+        .map_err(|e| format!("Failed to save assistant message: {}", e))?;
+
+    Ok(session_id)
+}
+
+// Re-run a session's user turns against another model and compare the replies; the session is unchanged
+#[command]
+pub async fn replay_session(
+    session_id: i64,
+    model: String,
+    cumulative: Option<bool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<ollama_api::ReplayResult, String> {
+    ollama_api::replay_session(session_id, model, cumulative.unwrap_or(false), state, db_conn, limiter).await
+}
+
+// Summarize a whole session on demand; the summary streams as `session-summary` events
+#[command]
+pub async fn summarize_session(
+    session_id: i64,
+    model: String,
+    window: tauri::Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
 ) -> Result<String, String> {
-    ollama_api::process_chat_generation(prompt, model, state, db_conn).await
+    ollama_api::summarize_session(session_id, model, window, state, db_conn, limiter).await
+}
+
+// Title a session again from its first prompt and reply; `title_model` is used when configured
+#[command]
+pub async fn regenerate_session_title(
+    session_id: i64,
+    model: String,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<String, String> {
+    ollama_api::title_session(session_id, &model, db_conn.inner(), limiter.inner()).await
+}
+
+// Embed all sessions that are new or changed since they were last embedded
+#[command]
+pub async fn index_session_embeddings(
+    db_conn: State<'_, db::DbPool>,
+) -> Result<usize, String> {
+    embeddings::index_sessions(db_conn.inner()).await
+}
+
+// Find sessions by meaning rather than keyword
+#[command]
+pub async fn semantic_search_sessions(
+    query: String,
+    limit: Option<usize>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<db::SessionMatch>, String> {
+    if query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    embeddings::semantic_search_sessions(db_conn.inner(), query.trim(), limit.unwrap_or(20)).await
+}
+
+// Star or unstar a message. Passing `favorite` sets that state; without it the current
+// state is flipped. Returns whether the message is now a favorite
+#[command]
+pub async fn toggle_message_favorite(
+    message_id: i64,
+    favorite: Option<bool>,
+    db: State<'_, db::DbPool>,
+) -> Result<bool, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::set_message_favorite(&conn, message_id, favorite).map_err(|e| e.to_string())
+}
+
+// List starred messages from every session, including archived ones, newest first
+#[command]
+pub async fn list_favorite_messages(
+    db: State<'_, db::DbPool>,
+) -> Result<Vec<db::FavoriteMessage>, String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    db::fetch_favorite_messages(&conn).map_err(|e| e.to_string())
+}
+
+// Find messages across all sessions by keyword
+#[command]
+pub async fn search_messages(
+    query: String,
+    limit: Option<i64>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<db::MessageSearchResult>, String> {
+    if query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::search_messages(&conn, query.trim(), limit.unwrap_or(50)).map_err(|e| e.to_string())
+}
+
+// Set the system prompt of a session; an empty prompt removes it
+#[command]
+pub async fn set_session_system_prompt(
+    session_id: i64,
+    prompt: Option<String>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    let prompt = prompt.as_deref().map(str::trim).filter(|p| !p.is_empty());
+    db::set_session_system_prompt(&conn, session_id, prompt).map_err(|e| e.to_string())
+}
+
+// Set the approximate token budget for the history sent with a session's requests; `None` or 0 sends everything
+#[command]
+pub async fn set_session_context_budget(
+    session_id: i64,
+    max_tokens: Option<i64>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    let max_tokens = max_tokens.filter(|t| *t > 0);
+    db::set_session_context_tokens(&conn, session_id, max_tokens).map_err(|e| e.to_string())
 }
 
+// Attach a text or markdown file to the session's next prompt; its content is sent ahead of
+// the prompt and kept in the history. Files over the `attachment_max_bytes` limit are refused
+#[command]
+pub async fn attach_file_to_prompt(
+    session_id: i64,
+    path: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::Attachment, String> {
+    let file_path = std::path::Path::new(&path);
+    let extension = file_path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    if !matches!(extension.as_deref(), Some("txt" | "md" | "markdown")) {
+        return Err(format!("Only .txt and .md files can be attached: {}", path));
+    }
+    let file_name = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.clone());
+
+    let max_bytes = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        settings::attachment_max_bytes(&conn)
+    };
+    let byte_count = std::fs::metadata(file_path)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?
+        .len();
+    if byte_count > max_bytes {
+        return Err(format!(
+            "{} is {} bytes, over the attachment limit of {} bytes",
+            file_name, byte_count, max_bytes
+        ));
+    }
+    let content = std::fs::read_to_string(file_path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::add_attachment(&conn, session_id, &file_name, &content, byte_count as i64).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => format!("Session {} not found", session_id),
+        e => e.to_string(),
+    })
+}
+
+// Get the generation settings stored on a session
+#[command]
+pub async fn get_session_config(
+    session_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::SessionConfig, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::get_session_config(&conn, session_id).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn list_personas(
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<db::Persona>, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::fetch_personas(&conn).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn create_persona(
+    persona: db::PersonaInput,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<i64, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::create_persona(&conn, &persona).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn update_persona(
+    persona_id: i64,
+    persona: db::PersonaInput,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::update_persona(&conn, persona_id, &persona).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn delete_persona(
+    persona_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::delete_persona(&conn, persona_id).map_err(|e| e.to_string())
+}
+
+// Save a reusable prompt; `{{name}}` placeholders are listed in the result's `variables`
+#[command]
+pub async fn save_prompt_template(
+    name: String,
+    content: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::PromptTemplate, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Template name cannot be empty".to_string());
+    }
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::create_prompt_template(&conn, name, &content).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn list_prompt_templates(
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<db::PromptTemplate>, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::fetch_prompt_templates(&conn).map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn update_prompt_template(
+    template_id: i64,
+    name: String,
+    content: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::PromptTemplate, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Template name cannot be empty".to_string());
+    }
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::update_prompt_template(&conn, template_id, name, &content).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => format!("Template {} not found", template_id),
+        e => e.to_string(),
+    })
+}
+
+#[command]
+pub async fn delete_prompt_template(
+    template_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::delete_prompt_template(&conn, template_id).map_err(|e| e.to_string())
+}
+
+// Copy a persona's model, system prompt and options onto a session
+#[command]
+pub async fn apply_persona(
+    session_id: i64,
+    persona_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::SessionConfig, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::apply_persona(&conn, session_id, persona_id)
+        .map_err(|e| format!("Failed to apply persona: {}", e))
+}
+
+// Pin a session to the digest the model tag currently resolves to
+#[command]
+pub async fn pin_session_model(
+    session_id: i64,
+    model: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<String, String> {
+    let digest = ollama_api::resolve_model_digest(&model).await?;
+
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::set_session_model_pin(&conn, session_id, &model, &digest).map_err(|e| e.to_string())?;
+
+    Ok(digest)
+}
+
+// Check whether the model a session is pinned to has changed since it was pinned
+#[command]
+pub async fn check_session_model(
+    session_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Option<ollama_api::ModelChangedWarning>, String> {
+    ollama_api::check_session_model_digest(session_id, db_conn.inner()).await
+}
+
+// Get all settings shown on the settings screen
+#[command]
+pub async fn get_settings(conn: State<'_, db::DbPool>) -> Result<Settings, String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    settings::load_settings(&conn).map_err(|e| e.to_string())
+}
+
+// Update several settings at once; fields left out are unchanged
+#[command]
+pub async fn update_settings(
+    conn: State<'_, db::DbPool>,
+    partial: SettingsUpdate,
+) -> Result<Settings, String> {
+    let mut conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    settings::update_settings(&mut conn, partial)
+}
+
+// Warn ahead of a slow first response when the session's model isn't loaded yet
+#[command]
+pub async fn predict_model_swap(
+    session_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<ollama_api::ModelSwapPrediction, String> {
+    ollama_api::predict_model_swap(session_id, db_conn.inner()).await
+}
+
+// Set a single known config key; an empty value deletes it
+#[command]
+pub async fn set_config(
+    conn: State<'_, db::DbPool>,
+    key: String,
+    value: Option<String>,
+) -> Result<(), String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    settings::set_config(&conn, &key, value.as_deref())
+}
+
+// Remove config keys left behind by removed features, returning them
+#[command]
+pub async fn cleanup_config(conn: State<'_, db::DbPool>) -> Result<Vec<String>, String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    settings::cleanup_config(&conn).map_err(|e| e.to_string())
+}
+
+// Get the keep_alive policy applied to every generation request
+#[command]
+pub async fn get_keep_alive_policy(
+    conn: State<'_, db::DbPool>,
+) -> Result<Option<String>, String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    db::get_config_value(&conn, "keep_alive").map_err(|e| e.to_string())
+}
+
+// Set the keep_alive policy (e.g. "5m", "0", "-1"); an empty value restores Ollama's default
+#[command]
+pub async fn set_keep_alive_policy(
+    conn: State<'_, db::DbPool>,
+    keep_alive: Option<String>,
+) -> Result<(), String> {
+    let conn = db::checkout(&conn).map_err(|e| e.to_string())?;
+    match keep_alive.filter(|v| !v.trim().is_empty()) {
+        Some(value) => {
+            ollama_api::parse_keep_alive(&value)?;
+            db::update_config_value(&conn, "keep_alive", value.trim())
+        }
+        None => db::delete_config_value(&conn, "keep_alive"),
+    }
+    .map_err(|e| e.to_string())
+}
+
+// Same as `get_keep_alive_policy`
+#[command]
+pub async fn get_keep_alive(
+    conn: State<'_, db::DbPool>,
+) -> Result<Option<String>, String> {
+    get_keep_alive_policy(conn).await
+}
+
+// Same as `set_keep_alive_policy`
+#[command]
+pub async fn set_keep_alive(
+    conn: State<'_, db::DbPool>,
+    keep_alive: Option<String>,
+) -> Result<(), String> {
+    set_keep_alive_policy(conn, keep_alive).await
+}
+
+// Save a batch of messages into a session in one transaction
+#[command]
+pub async fn save_messages_bulk(
+    session_id: i64,
+    messages: Vec<db::NewChatMessage>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<usize, String> {
+    db::save_messages_bulk(session_id, &messages, db_conn)
+        .await
+        .map_err(|e| format!("Failed to save messages: {}", e))
+}
+
+// Import every conversation of a ChatGPT `conversations.json` export as a new session;
+// returns the number of sessions created
+#[command]
+pub async fn import_chatgpt_export(
+    path: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<usize, String> {
+    let file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let conversations: Vec<importer::ChatGptConversation> = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| format!("{} is not a ChatGPT conversations export: {}", path, e))?;
+
+    let mut imported = 0;
+    for conversation in &conversations {
+        let messages = conversation.messages();
+        if messages.is_empty() {
+            continue;
+        }
+
+        let mut conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        let title = conversation.title();
+        let session_id = db::create_session(&conn, &title).map_err(|e| e.to_string())?;
+        db::insert_messages(&mut conn, session_id, &messages)
+            .map_err(|e| format!("Failed to import \"{}\": {}", title, e))?;
+        let created_at = conversation.create_time.and_then(importer::sqlite_timestamp);
+        let updated_at = conversation.update_time.and_then(importer::sqlite_timestamp);
+        db::set_session_times(&conn, session_id, created_at.as_deref(), updated_at.as_deref())
+            .map_err(|e| e.to_string())?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+// Build the JSON the frontend and Ollama expect for each message, with its images and attachments
+fn history_json(
+    chat_messages: Vec<db::ChatMessage>,
+    conn: &Connection,
+    session_id: i64,
+) -> Result<Vec<Value>, String> {
+    let mut images = db::fetch_session_images(conn, session_id).map_err(|e| e.to_string())?;
+    let mut attachments = db::fetch_session_attachments(conn, session_id).map_err(|e| e.to_string())?;
+    let mut params = db::fetch_session_generation_params(conn, session_id).map_err(|e| e.to_string())?;
+    let favorites = db::fetch_session_favorites(conn, session_id).map_err(|e| e.to_string())?;
+
+    let json_messages = chat_messages
+        .into_iter()
+        .map(|msg| {
+            let mut message = serde_json::json!({
+                "id": msg.id,
+                "session_id": msg.session_id,
+                "role": msg.role,
+                "content": msg.message,
+                "timestamp": msg.timestamp,
+                "annotation": msg.annotation,
+                "stopped": msg.stopped,
+                "truncated": msg.truncated,
+                "is_favorite": favorites.contains(&msg.id),
+                "char_count": msg.message.chars().count(),
+                "token_count": ollama_api::approx_token_count(&msg.message)
+            });
+            // Reattach images so vision models see them again when the history is replayed
+            if let Some(images) = images.remove(&msg.id) {
+                message["images"] = serde_json::json!(images);
+            }
+            // Shown as a collapsible note above the prompt; inlined into the prompt for the model
+            if let Some(attachments) = attachments.remove(&msg.id) {
+                message["attachments"] = serde_json::json!(attachments);
+            }
+            // Replies carry what produced them, since the session's model and options can change later
+            if let Some(params) = params.remove(&msg.id) {
+                message["model"] = serde_json::json!(params.model);
+                message["temperature"] = serde_json::json!(params.temperature);
+                message["seed"] = serde_json::json!(params.seed);
+                message["num_ctx"] = serde_json::json!(params.num_ctx);
+            }
+            message
+        })
+        .collect();
+
+    Ok(json_messages)
+}
+
+// Count a session's messages and approximate tokens, with its first and last message times
+#[command]
+pub async fn get_session_stats(
+    session_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::SessionStats, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::fetch_session_stats(&conn, session_id).map_err(|e| e.to_string())
+}
+
+// Load all of the current session's messages; `load_chat_history_page` loads them in pages
 #[command]
 pub async fn load_chat_history(
     state: State<'_, Arc<Mutex<GenerationState>>>,
-    db_conn: State<'_, Arc<Mutex<rusqlite::Connection>>>,
+    db_conn: State<'_, db::DbPool>,
 ) -> Result<Vec<Value>, String> {
+    load_chat_history_page(None, None, None, state, db_conn).await.map(|page| page.messages)
+}
 
+// Load a page of the current session's messages for lazy loading on scroll, with the total
+// count so the UI knows when it has reached the top; without a limit the whole session loads.
+// It is separate from `load_chat_history` because that returns a bare array, which the chat
+// window and the context building for generation rely on
+#[command]
+pub async fn load_chat_history_page(
+    offset: Option<i64>,
+    limit: Option<i64>,
+    newest_first: Option<bool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::HistoryPage, String> {
     let session_id = {
         let state_guard = state.lock().await;
         state_guard.current_session_id.unwrap_or(-1)
     };
-    
-    let chat_messages = db::fetch_chat_history(session_id, db_conn)
-    .await
-    .map_err(|e| e.to_string())?;
+    let offset = offset.unwrap_or(0).max(0);
+    let limit = limit.filter(|l| *l >= 0);
 
-    let json_messages: Vec<Value> = chat_messages
-        .into_iter()
-        .map(|msg| serde_json::json!({
-            "id": msg.id,
-            "session_id": msg.session_id,
-            "role": msg.role,
-            "content": msg.message,
-            "timestamp": msg.timestamp
-        }))
-        .collect();
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    let chat_messages = db::fetch_chat_history_page(&conn, session_id, offset, limit, newest_first.unwrap_or(false))
+        .map_err(|e| e.to_string())?;
+    let total = db::count_session_messages(&conn, session_id).map_err(|e| e.to_string())?;
 
-    Ok(json_messages)
+    Ok(db::HistoryPage {
+        messages: history_json(chat_messages, &conn, session_id)?,
+        total,
+    })
+}
+
+// Add a hand-written message to a session without generating, e.g. for few-shot examples
+#[command]
+pub async fn add_manual_message(
+    session_id: i64,
+    role: String,
+    content: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<i64, String> {
+    if !matches!(role.as_str(), "user" | "assistant" | "system") {
+        return Err(format!("Invalid role '{}': expected user, assistant or system", role));
+    }
+    if content.trim().is_empty() {
+        return Err("Message content must not be empty".to_string());
+    }
+    {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        if db::get_session_title(&conn, session_id).map_err(|e| e.to_string())?.is_none() {
+            return Err(format!("Session {} does not exist", session_id));
+        }
+    }
+
+    db::save_chat_message(session_id, &role, &content, None, db_conn)
+        .await
+        .map_err(|e| e.to_string())
 }
 
-// Abort chat generation
+// Get a session's history paired into user/assistant turns
 #[command]
-pub async fn abort_generation(state: tauri::State<'_, Arc<Mutex<GenerationState>>>) -> Result<(), String> {
-    let mut generation_state = state.lock().await;
+pub async fn fetch_chat_turns(
+    session_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<db::ChatTurn>, String> {
+    let messages = db::fetch_chat_history(session_id, db_conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(db::group_turns(messages))
+}
+
+// Attach a note to a message, or remove it with `None`
+#[command]
+pub async fn set_message_annotation(
+    message_id: i64,
+    note: Option<String>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    db::set_message_annotation(message_id, note, db_conn)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Change the content of a message and drop everything after it, returning the remaining history
+#[command]
+pub async fn edit_message(
+    message_id: i64,
+    new_content: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Vec<db::ChatMessage>, String> {
+    let session_id = {
+        let mut conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        db::edit_message(&mut conn, message_id, &new_content).map_err(|e| e.to_string())?
+    };
+    db::fetch_chat_history(session_id, db_conn).await.map_err(|e| e.to_string())
+}
+
+// Delete one message; `mid_conversation` is set when messages after it remain
+#[command]
+pub async fn delete_message(
+    message_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<db::DeletedMessage, String> {
+    let mut conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::delete_message(&mut conn, message_id).map_err(|e| e.to_string())
+}
 
-    if let Some(token) = &generation_state.cancellation_token {
-        token.cancel(); // Trigger cancellation
+// Get token counts, timings and tokens per second of an assistant message
+#[command]
+pub async fn get_message_stats(
+    message_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<Option<ollama_api::GenerationStats>, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    db::get_message_stats(&conn, message_id).map_err(|e| e.to_string())
+}
+
+// Export per-message generation metrics as CSV
+#[command]
+pub async fn export_metrics_csv(
+    db_conn: State<'_, db::DbPool>,
+) -> Result<String, String> {
+    let metrics = db::fetch_message_metrics(db_conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(export::metrics_csv(&metrics))
+}
+
+// Build the fine-tuning JSONL line for an assistant message and the user turn before it
+fn pair_line(conn: &Connection, message_id: i64) -> Result<String, String> {
+    let message = db::get_chat_message(conn, message_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Message {} not found", message_id))?;
+    if message.role != "assistant" {
+        return Err(format!("Message {} is not an assistant message", message_id));
     }
 
-    generation_state.is_running = false; // Update state to indicate generation is no longer running
-    generation_state.cancellation_token = None; // Clear the cancellation token
+    let user = match db::fetch_previous_message(conn, message.session_id, message.id).map_err(|e| e.to_string())? {
+        Some((role, content)) if role == "user" => content,
+        _ => return Err(format!("Message {} has no preceding user turn", message_id)),
+    };
+    let system_prompt = db::get_session_config(conn, message.session_id)
+        .map_err(|e| e.to_string())?
+        .system_prompt
+        .filter(|p| !p.trim().is_empty());
+
+    Ok(export::pair_jsonl(
+        system_prompt.as_deref(),
+        &user,
+        &ollama_api::strip_think(&message.message),
+    ))
+}
 
+// Export an assistant message with its prompt as a fine-tuning JSONL line
+#[command]
+pub async fn export_pair_jsonl(
+    message_id: i64,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<String, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    pair_line(&conn, message_id)
+}
+
+// Export several exchanges as a JSONL dataset, one line per assistant message
+#[command]
+pub async fn export_pairs_jsonl(
+    message_ids: Vec<i64>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<String, String> {
+    let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+    let mut jsonl = String::new();
+    for message_id in message_ids {
+        jsonl.push_str(&pair_line(&conn, message_id)?);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+// Copy a session to the OS clipboard as Markdown or plain text
+#[command]
+pub async fn copy_session_to_clipboard(
+    session_id: i64,
+    format: export::ExportFormat,
+    app: tauri::AppHandle,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let (title, model) = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        let title = db::get_session_title(&conn, session_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Session {} not found", session_id))?;
+        (title, db::get_session_config(&conn, session_id).map_err(|e| e.to_string())?.model)
+    };
+
+    let messages = db::fetch_chat_history(session_id, db_conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    app.clipboard_manager()
+        .write_text(export::render_session(format, session_id, &title, model.as_deref(), &messages))
+        .map_err(|e| format!("Failed to copy to clipboard: {}", e))
+}
+
+// Render a session as Markdown. With `save_to_file`, also asks for a path and writes the document there.
+#[command]
+pub async fn export_session_markdown(
+    session_id: i64,
+    save_to_file: Option<bool>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<String, String> {
+    let (title, model) = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        let title = db::get_session_title(&conn, session_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Session {} not found", session_id))?;
+        (title, db::get_session_config(&conn, session_id).map_err(|e| e.to_string())?.model)
+    };
+
+    let messages = db::fetch_chat_history(session_id, db_conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let markdown = export::session_markdown(&title, model.as_deref(), &messages);
+
+    if save_to_file.unwrap_or(false) {
+        let path = tauri::api::dialog::blocking::FileDialogBuilder::new()
+            .set_file_name(&format!("{}.md", title.replace(['/', '\\', ':'], "-")))
+            .add_filter("Markdown", &["md"])
+            .save_file();
+        // A cancelled dialog still returns the document
+        if let Some(path) = path {
+            std::fs::write(&path, &markdown).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+    }
+
+    Ok(markdown)
+}
+
+// Messages read from the database per batch when exporting to a file
+const EXPORT_BATCH_SIZE: i64 = 200;
+
+// Export a session to a file in batches, emitting `export-progress` events; returns the number of messages written
+#[command]
+pub async fn export_session_to_file(
+    session_id: i64,
+    path: String,
+    format: export::ExportFormat,
+    window: tauri::Window,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<usize, String> {
+    let (title, model, total) = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        let title = db::get_session_title(&conn, session_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Session {} not found", session_id))?;
+        let model = db::get_session_config(&conn, session_id).map_err(|e| e.to_string())?.model;
+        let total = db::count_session_messages(&conn, session_id).map_err(|e| e.to_string())?;
+        (title, model, total as usize)
+    };
+
+    let file = std::fs::File::create(&path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    let mut writer = export::SessionWriter::begin(std::io::BufWriter::new(file), format, session_id, &title, model.as_deref())
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    let mut after_id = 0;
+    loop {
+        // Only hold the database lock while reading a batch
+        let batch = {
+            let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+            db::fetch_chat_history_batch(&conn, session_id, after_id, EXPORT_BATCH_SIZE).map_err(|e| e.to_string())?
+        };
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for message in &batch {
+            writer.write_message(message).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        }
+
+        let _ = window.emit("export-progress", export::ExportProgress {
+            session_id,
+            written: writer.written(),
+            total,
+        });
+    }
+
+    let written = writer.written();
+    writer.finish().map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(written)
+}
+
+// Back up every session with its messages and settings to one JSON file; returns the number of sessions written
+#[command]
+pub async fn export_all_json(
+    path: String,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<usize, String> {
+    let sessions = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        db::fetch_backup_sessions(&conn).map_err(|e| e.to_string())?
+    };
+
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", path, e);
+    let file = std::fs::File::create(&path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    let mut writer = export::BackupWriter::begin(std::io::BufWriter::new(file)).map_err(write_error)?;
+
+    for (session_id, session) in &sessions {
+        writer.begin_session(session).map_err(write_error)?;
+        let mut after_id = 0;
+        loop {
+            // Only hold the database lock while reading a batch
+            let batch = {
+                let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+                db::fetch_backup_messages_batch(&conn, *session_id, after_id, EXPORT_BATCH_SIZE)
+                    .map_err(|e| e.to_string())?
+            };
+            let Some((last_id, _)) = batch.last() else {
+                break;
+            };
+            after_id = *last_id;
+
+            for (_, message) in &batch {
+                writer.write_message(message).map_err(write_error)?;
+            }
+        }
+        writer.end_session().map_err(write_error)?;
+    }
+
+    let written = writer.sessions();
+    writer.finish().map_err(write_error)?;
+    Ok(written)
+}
+
+// Restore sessions from a file written by `export_all_json`. Sessions whose title already exists
+// are skipped, or with `merge` receive the messages they are missing.
+#[command]
+pub async fn import_all_json(
+    path: String,
+    merge: Option<bool>,
+    db_conn: State<'_, db::DbPool>,
+) -> Result<export::BackupImportSummary, String> {
+    let file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let backup: export::Backup = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| format!("{} is not a valid backup: {}", path, e))?;
+    if backup.version > export::BACKUP_VERSION {
+        return Err(format!(
+            "{} was written by a newer version of the app (backup version {})",
+            path, backup.version
+        ));
+    }
+
+    let merge = merge.unwrap_or(false);
+    let mut summary = export::BackupImportSummary::default();
+    for entry in &backup.sessions {
+        let mut conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        match db::import_backup_session(&mut conn, &entry.session, &entry.messages, merge)
+            .map_err(|e| format!("Failed to import \"{}\": {}", entry.session.title, e))?
+        {
+            db::BackupImport::Created => {
+                summary.created += 1;
+                summary.messages += entry.messages.len();
+            }
+            db::BackupImport::Merged { added } => {
+                summary.merged += 1;
+                summary.messages += added;
+            }
+            db::BackupImport::Skipped => summary.skipped += 1,
+        }
+    }
+    Ok(summary)
+}
+
+// Abort the generation of a session; without a session id the running generation is aborted.
+// Aborting a session that isn't generating does nothing
+#[command]
+pub async fn abort_generation(
+    session_id: Option<i64>,
+    state: tauri::State<'_, Arc<Mutex<GenerationState>>>,
+) -> Result<(), String> {
+    let mut state = state.lock().await;
+    match session_id {
+        Some(session_id) => {
+            state.abort_session(session_id);
+        }
+        None => state.abort(),
+    }
     Ok(())
 }
+
+// Set (or clear with `None`) the global hotkey that aborts generation, e.g. "CmdOrCtrl+Shift+X"
+#[command]
+pub async fn set_abort_hotkey(
+    keys: Option<String>,
+    app: tauri::AppHandle,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    let previous = db::get_config_value(&conn, shortcuts::ABORT_HOTKEY_KEY).map_err(|e| e.to_string())?;
+
+    match keys.map(|k| k.trim().to_string()).filter(|k| !k.is_empty()) {
+        Some(keys) => {
+            shortcuts::register_abort_hotkey(&app, previous.as_deref(), &keys)?;
+            db::update_config_value(&conn, shortcuts::ABORT_HOTKEY_KEY, &keys)
+        }
+        None => {
+            if let Some(previous) = previous {
+                shortcuts::unregister(&app, &previous);
+            }
+            db::delete_config_value(&conn, shortcuts::ABORT_HOTKEY_KEY)
+        }
+    }
+    .map_err(|e| e.to_string())
+}
+
+// Set the global shortcut that opens the quick-chat window, e.g. "CmdOrCtrl+Shift+Space";
+// `None` restores the default. Fails, keeping the old shortcut, when the new one is taken
+#[command]
+pub async fn set_global_shortcut(
+    keys: Option<String>,
+    app: tauri::AppHandle,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    let previous = shortcuts::quick_chat_hotkey(&conn).map_err(|e| e.to_string())?;
+
+    match keys.map(|k| k.trim().to_string()).filter(|k| !k.is_empty()) {
+        Some(keys) => {
+            shortcuts::register_quick_chat_hotkey(&app, Some(&previous), &keys)?;
+            db::update_config_value(&conn, shortcuts::QUICK_CHAT_HOTKEY_KEY, &keys)
+        }
+        None => {
+            shortcuts::register_quick_chat_hotkey(&app, Some(&previous), shortcuts::DEFAULT_QUICK_CHAT_HOTKEY)?;
+            db::delete_config_value(&conn, shortcuts::QUICK_CHAT_HOTKEY_KEY)
+        }
+    }
+    .map_err(|e| e.to_string())
+}
+
+// Reset the main window to its default size, centered on the primary monitor
+#[command]
+pub async fn reset_window_geometry(
+    window: tauri::Window,
+    db: State<'_, db::DbPool>,
+) -> Result<(), String> {
+    let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+    window_state::reset_window_geometry(&window, &conn)
+}
+
+// Dump the current generation state; only available in debug builds or with `debug_mode` enabled
+#[command]
+pub async fn debug_generation_state(
+    db: State<'_, db::DbPool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+) -> Result<GenerationStateSnapshot, String> {
+    let debug_mode = {
+        let conn = db::checkout(&db).map_err(|e| e.to_string())?;
+        db::get_config_value(&conn, "debug_mode")
+            .map_err(|e| e.to_string())?
+            .map(|value| value == "true")
+            .unwrap_or(false)
+    };
+
+    if !cfg!(debug_assertions) && !debug_mode {
+        return Err("Debug mode is not enabled".to_string());
+    }
+
+    Ok(state.lock().await.snapshot())
+}
+
+// Check that the database directory is writable
+#[command]
+pub async fn check_db_writable() -> Result<(), String> {
+    db::check_dir_writable(&db::db_dir())
+}
+
+// Report the data directory in use and any configured overrides
+#[command]
+pub async fn get_data_dir() -> Result<db::DataDirInfo, String> {
+    Ok(db::DataDirInfo {
+        current: db::db_dir().display().to_string(),
+        default: db::default_db_dir().display().to_string(),
+        saved_override: db::data_dir_override().map_err(|e| e.to_string())?.map(|d| d.display().to_string()),
+        env_override: std::env::var(db::DATA_DIR_ENV).ok().filter(|d| !d.is_empty()),
+    })
+}
+
+// Move the data directory from the next start, or restore the default with `None`. The
+// directory is created and checked for write access first; existing chats are not moved
+#[command]
+pub async fn set_data_dir(path: Option<String>) -> Result<(), String> {
+    let path = path.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    db::set_data_dir_override(path.as_deref().map(std::path::Path::new))
+}
@@ -1,20 +1,103 @@
 
 // Handles SQLite database operations
 
+use crate::ollama_api::{GenerationOptions, GenerationStats};
 use crate::session::GenerationState;
-use rusqlite::{params, Connection, Result, OptionalExtension};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, DatabaseName, OpenFlags, Result, OptionalExtension};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tauri::State;
 use tauri::api::path::app_data_dir;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 
 #[derive(Debug, serde::Serialize)]
 pub struct ChatSession {
    pub id: i64,
     pub title: String,
+    /// Pinned sessions are listed before all others.
+    pub pinned: bool,
+    /// Archived sessions are left out of the session list unless requested.
+    pub archived: bool,
+    pub folder_id: Option<i64>,
+    /// Model the session chats with; prompts sent without a model use it.
+    pub model: Option<String>,
+}
+
+/// A text file attached to a prompt. `message_id` is `None` until the next prompt is sent.
+#[derive(Debug, serde::Serialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub session_id: i64,
+    pub message_id: Option<i64>,
+    pub file_name: String,
+    pub content: String,
+    pub byte_count: i64,
+    pub created_at: String,
+}
+
+fn attachment_from_row(row: &rusqlite::Row) -> Result<Attachment> {
+    Ok(Attachment {
+        id: row.get(0)?,
+        session_id: row.get(1)?,
+        message_id: row.get(2)?,
+        file_name: row.get(3)?,
+        content: row.get(4)?,
+        byte_count: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+/// Rows removed by `clear_all_data`.
+#[derive(Debug, serde::Serialize)]
+pub struct ClearedData {
+    pub sessions: usize,
+    pub messages: usize,
+}
+
+/// A named group of sessions.
+#[derive(Debug, serde::Serialize)]
+pub struct Folder {
+    pub id: i64,
+    pub name: String,
+    pub created_at: String,
+}
+
+/// Number of sessions in the default list and hidden by archiving.
+#[derive(Debug, serde::Serialize)]
+pub struct SessionCounts {
+    pub active: i64,
+    pub archived: i64,
+}
+
+/// Size of a conversation, computed from its messages when requested.
+#[derive(Debug, serde::Serialize)]
+pub struct SessionStats {
+    pub session_id: i64,
+    pub message_count: i64,
+    pub char_count: i64,
+    /// Approximate, at four characters per token like the per-message `token_count`.
+    pub token_count: i64,
+    pub first_timestamp: Option<String>,
+    pub last_timestamp: Option<String>,
+}
+
+/// The model and sampling settings a reply was generated with; settings left to Ollama's defaults are `None`.
+#[derive(Debug, serde::Serialize)]
+pub struct GenerationParams {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub seed: Option<i64>,
+    pub num_ctx: Option<i64>,
+}
+
+/// A page of the current session's messages and the session's total message count.
+#[derive(Debug, serde::Serialize)]
+pub struct HistoryPage {
+    pub messages: Vec<serde_json::Value>,
+    pub total: i64,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -30,36 +113,540 @@ pub struct ChatMessage {
     pub session_id: i64,
     pub role: String,
     pub message: String,
+    /// RFC 3339 in UTC, e.g. "2024-05-01T12:30:00Z".
     pub timestamp: String,
+    pub annotation: Option<String>,
+    pub stopped: bool,
+    pub truncated: bool,
+    /// Reasoning the model produced before this reply, stored apart from the reply itself.
+    pub thinking: Option<String>,
 }
 
+/// A user message paired with the assistant reply that follows it. Either side is `None`
+/// when a message has no counterpart, e.g. a prompt still awaiting its reply.
+#[derive(Debug, serde::Serialize)]
+pub struct ChatTurn {
+    pub user: Option<ChatMessage>,
+    pub assistant: Option<ChatMessage>,
+}
 
-// Initialize SQLite Database
-pub fn init_db() -> Arc<Mutex<Connection>> {
+/// A message to insert with `save_messages_bulk`, e.g. from an import.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct NewChatMessage {
+    pub role: String,
+    pub content: String,
+    /// Original timestamp of the message, e.g. "2024-05-01 12:30:00" (UTC) or RFC 3339;
+    /// stored as RFC 3339 UTC and defaulting to now when absent or unparseable.
+    pub timestamp: Option<String>,
+}
+
+/// Settings of a session as stored in a full JSON backup.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct BackupSession {
+    pub title: String,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub options: Option<GenerationOptions>,
+    pub show_thinking: Option<bool>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+/// A message as stored in a full JSON backup, with its generation stats.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct BackupMessage {
+    pub role: String,
+    pub message: String,
+    pub timestamp: Option<String>,
+    pub model: Option<String>,
+    pub annotation: Option<String>,
+    #[serde(default)]
+    pub stopped: bool,
+    #[serde(default)]
+    pub truncated: bool,
+    #[serde(default)]
+    pub thinking: Option<String>,
+    pub stats: Option<GenerationStats>,
+}
+
+/// What `import_backup_session` did with a session from a backup.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BackupImport {
+    Created,
+    /// Messages were added to an existing session with the same title.
+    Merged { added: usize },
+    /// A session with the same title exists and merging was not requested.
+    Skipped,
+}
+
+/// Generation metrics of one assistant message, as exported to CSV.
+#[derive(Debug, serde::Serialize)]
+pub struct MessageMetrics {
+    pub session_id: i64,
+    pub message_id: i64,
+    pub timestamp: String,
+    pub model: Option<String>,
+    pub prompt_eval_count: Option<i64>,
+    pub eval_count: Option<i64>,
+    pub tokens_per_second: Option<f64>,
+}
+
+/// Per-session generation settings.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SessionConfig {
+    pub session_id: i64,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub options: Option<GenerationOptions>,
+    pub show_thinking: Option<bool>,
+    /// Approximate token budget for the history sent with each request; unlimited when unset.
+    pub context_tokens: Option<i64>,
+}
+
+/// A reusable bundle of model, system prompt and options that can be applied to sessions.
+#[derive(Debug, serde::Serialize)]
+pub struct Persona {
+    pub id: i64,
+    pub label: String,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub options: Option<GenerationOptions>,
+}
+
+/// Fields for creating or updating a persona.
+#[derive(Debug, serde::Deserialize)]
+pub struct PersonaInput {
+    pub label: String,
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    pub options: Option<GenerationOptions>,
+}
+
+/// A reusable prompt scaffold that the UI inserts into the input box.
+#[derive(Debug, serde::Serialize)]
+pub struct PromptTemplate {
+    pub id: i64,
+    pub name: String,
+    pub content: String,
+    pub created_at: String,
+    /// Names of the `{{variable}}` placeholders in `content`, in order of first use.
+    /// The UI asks for their values and substitutes them itself.
+    pub variables: Vec<String>,
+}
+
+/// One recorded session or model action.
+#[derive(Debug, serde::Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub action: String,
+    pub target_id: Option<i64>,
+    pub detail: Option<String>,
+    pub timestamp: String,
+}
+
+/// A session ranked by `semantic_search_sessions`.
+#[derive(Debug, serde::Serialize)]
+pub struct SessionMatch {
+    pub id: i64,
+    pub title: String,
+    pub score: f32,
+}
+
+/// A message matched by `search_messages`, with its session's title and the text around the match.
+#[derive(Debug, serde::Serialize)]
+pub struct MessageSearchResult {
+    pub message: ChatMessage,
+    pub session_title: String,
+    pub snippet: String,
+}
+
+/// A starred message with the session it belongs to.
+#[derive(Debug, serde::Serialize)]
+pub struct FavoriteMessage {
+    pub message: ChatMessage,
+    pub session_title: String,
+    /// Favorites stay listed when their session is archived.
+    pub session_archived: bool,
+}
+
+/// Outcome of `delete_message`.
+#[derive(Debug, serde::Serialize)]
+pub struct DeletedMessage {
+    pub session_id: i64,
+    /// Later messages remain, so the conversation sent to the model now has a gap.
+    pub mid_conversation: bool,
+}
+
+pub const AUDIT_SESSION_CREATED: &str = "session_created";
+pub const AUDIT_SESSION_RENAMED: &str = "session_renamed";
+pub const AUDIT_SESSION_DELETED: &str = "session_deleted";
+
+/// The data directory in use and the overrides that choose it at startup.
+#[derive(Debug, serde::Serialize)]
+pub struct DataDirInfo {
+    pub current: String,
+    pub default: String,
+    /// Set by `set_data_dir`; `OLLAMA_CHAT_DATA_DIR` takes precedence over it.
+    pub saved_override: Option<String>,
+    pub env_override: Option<String>,
+}
+
+/// Environment variable that moves the data directory, e.g. onto an encrypted volume.
+pub const DATA_DIR_ENV: &str = "OLLAMA_CHAT_DATA_DIR";
+// File in the default data directory naming the override set with `set_data_dir_override`
+const DATA_DIR_OVERRIDE_FILE: &str = "data_dir_override";
+
+// Resolved on first use so the database never moves while the app runs
+static DATA_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+/// The platform's app data directory, used unless an override is set.
+pub fn default_db_dir() -> PathBuf {
     // Get the app data directory for the platform
-    let base_dir = app_data_dir(&tauri::Config::default())
+    app_data_dir(&tauri::Config::default())
         .expect("Failed to retrieve application data directory")
-        .join("OllamaChat");
+        .join("OllamaChat")
+}
+
+/// Returns the directory holding the database file.
+pub fn db_dir() -> PathBuf {
+    DATA_DIR.get_or_init(resolve_db_dir).clone()
+}
+
+// Use `OLLAMA_CHAT_DATA_DIR`, else the saved override, else the default. An override that can't
+// be created or written to is skipped with a warning instead of failing startup
+fn resolve_db_dir() -> PathBuf {
+    let default = default_db_dir();
+    let override_dir = std::env::var_os(DATA_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| data_dir_override().ok().flatten());
+
+    match override_dir {
+        Some(dir) => match prepare_data_dir(&dir) {
+            Ok(()) => dir,
+            Err(e) => {
+                eprintln!("Ignoring data directory override: {} Using {}", e, default.display());
+                default
+            }
+        },
+        None => default,
+    }
+}
+
+/// Creates `dir` if needed and checks that it is writable.
+pub fn prepare_data_dir(dir: &Path) -> std::result::Result<(), String> {
+    if !dir.is_absolute() {
+        return Err(format!("The data directory {} must be an absolute path.", dir.display()));
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create the data directory {}: {}.", dir.display(), e))?;
+    check_dir_writable(dir)
+}
+
+/// Returns the data directory saved with `set_data_dir_override`, if any.
+pub fn data_dir_override() -> std::io::Result<Option<PathBuf>> {
+    match fs::read_to_string(default_db_dir().join(DATA_DIR_OVERRIDE_FILE)) {
+        Ok(dir) => Ok(Some(dir.trim()).filter(|d| !d.is_empty()).map(PathBuf::from)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves (or with `None` removes) the data directory to use from the next start.
+/// It lives in a file beside the default database, since the database itself may move.
+pub fn set_data_dir_override(dir: Option<&Path>) -> std::result::Result<(), String> {
+    let file = default_db_dir().join(DATA_DIR_OVERRIDE_FILE);
+    match dir {
+        Some(dir) => {
+            prepare_data_dir(dir)?;
+            fs::create_dir_all(default_db_dir()).map_err(|e| e.to_string())?;
+            fs::write(&file, dir.to_string_lossy().as_bytes()).map_err(|e| e.to_string())
+        }
+        None => match fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Returns the path of the database file.
+pub fn db_path() -> PathBuf {
+    db_dir().join("OllamaChat.db")
+}
+
+/// Verifies that `dir` is writable by creating and deleting a small probe file.
+pub fn check_dir_writable(dir: &Path) -> std::result::Result<(), String> {
+    let probe = dir.join(".write_test");
+    let not_writable = |e: std::io::Error| {
+        format!("The data directory {} is not writable: {}. Please check its permissions.", dir.display(), e)
+    };
+
+    fs::write(&probe, b"ok").map_err(not_writable)?;
+    fs::remove_file(&probe).map_err(not_writable)
+}
+
+/// Pool of connections to the app database, shared as Tauri state.
+pub type DbPool = r2d2::Pool<SqliteConnectionManager>;
+pub type PooledConnection = r2d2::PooledConnection<SqliteConnectionManager>;
+
+// Readers run concurrently in WAL mode; writers still take turns via the busy timeout
+const POOL_SIZE: u32 = 4;
+
+/// Checks a connection out of the pool, waiting while all of them are in use.
+/// A pool timeout is reported as `SQLITE_BUSY`, like any other lock wait that gave up.
+pub fn checkout(pool: &DbPool) -> Result<PooledConnection> {
+    pool.get().map_err(|e| {
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), Some(e.to_string()))
+    })
+}
+
+// How long a statement waits for a lock held by another connection before failing
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(5000);
+
+/// Switches the connection to WAL with `synchronous=NORMAL` and sets a busy timeout, so
+/// streaming saves don't fsync on every write and brief lock contention waits instead of
+/// failing with "database is locked". Returns the journal mode SQLite actually applied.
+pub fn configure_connection(conn: &Connection) -> Result<String> {
+    let journal_mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(journal_mode)
+}
+
+// Number of pre-migration backups kept next to the database
+const MAX_DB_BACKUPS: usize = 3;
+
+// Copy the database file to a timestamped `.bak` beside it, pruning the oldest backups
+fn backup_db_file(db_path: &Path) -> std::result::Result<PathBuf, String> {
+    let file_name = db_path.file_name().and_then(|n| n.to_str()).unwrap_or("OllamaChat.db");
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let backup_path = db_path.with_file_name(format!("{}.{}.bak", file_name, stamp));
+
+    fs::copy(db_path, &backup_path)
+        .map_err(|e| format!("Failed to back up the database to {}: {}", backup_path.display(), e))?;
+
+    if let Some(dir) = db_path.parent() {
+        let prefix = format!("{}.", file_name);
+        let mut backups: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|n| n.to_str())
+                            .map(|n| n.starts_with(&prefix) && n.ends_with(".bak"))
+                            .unwrap_or(false)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Timestamps sort chronologically by name
+        backups.sort();
+        let excess = backups.len().saturating_sub(MAX_DB_BACKUPS);
+        for old in &backups[..excess] {
+            if let Err(e) = fs::remove_file(old) {
+                eprintln!("Failed to remove old database backup {}: {}", old.display(), e);
+            }
+        }
+    }
+
+    Ok(backup_path)
+}
+
+/// Why the database could not be opened at startup.
+#[derive(Debug)]
+pub enum DbInitError {
+    /// The data directory can't be created or written to; resetting the database won't help.
+    DataDir(String),
+    /// The database file can't be opened, read or upgraded, e.g. because it is corrupt.
+    Database(String),
+}
+
+impl DbInitError {
+    /// Whether moving the database aside and starting with an empty one could fix this.
+    pub fn can_reset(&self) -> bool {
+        matches!(self, DbInitError::Database(_))
+    }
+}
+
+impl std::fmt::Display for DbInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbInitError::DataDir(message) | DbInitError::Database(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Moves the database file and its WAL files aside as `<name>.<timestamp>.corrupt` so the next
+/// `init_db` starts empty. Returns the path the database was moved to.
+pub fn reset_database() -> std::result::Result<PathBuf, String> {
+    let db_path = db_path();
+    let file_name = db_path.file_name().and_then(|n| n.to_str()).unwrap_or("OllamaChat.db");
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let moved_to = db_path.with_file_name(format!("{}.{}.corrupt", file_name, stamp));
+
+    fs::rename(&db_path, &moved_to)
+        .map_err(|e| format!("Failed to move the database to {}: {}", moved_to.display(), e))?;
+    // Stale WAL files would otherwise be applied to the new, empty database
+    for suffix in ["-wal", "-shm"] {
+        let path = db_path.with_file_name(format!("{}{}", file_name, suffix));
+        if path.exists() {
+            let target = db_path.with_file_name(format!("{}.{}.corrupt{}", file_name, stamp, suffix));
+            if let Err(e) = fs::rename(&path, &target) {
+                eprintln!("Failed to move {} aside: {}", path.display(), e);
+            }
+        }
+    }
+    Ok(moved_to)
+}
 
-    let db_path: PathBuf = base_dir.join("OllamaChat.db");
+// Initialize SQLite Database
+pub fn init_db() -> std::result::Result<DbPool, DbInitError> {
+    let base_dir = db_dir();
+    let db_path = db_path();
 
     // Ensure the directory exists
     if let Some(parent) = db_path.parent() {
         if !parent.exists() {
-            fs::create_dir_all(parent).expect("Failed to create database directory");
+            fs::create_dir_all(parent).map_err(|e| {
+                DbInitError::DataDir(format!("Failed to create the data directory {}: {}", parent.display(), e))
+            })?;
+        }
+    }
+
+    // Fail early with a clear message rather than deep inside a SQL statement
+    check_dir_writable(&base_dir).map_err(DbInitError::DataDir)?;
+
+    let existed = db_path.exists();
+    let conn = Connection::open(&db_path)
+        .map_err(|e| DbInitError::Database(format!("Failed to open the database {}: {}", db_path.display(), e)))?;
+
+    // WAL can be refused (e.g. on some network filesystems); SQLite then keeps the old mode
+    match configure_connection(&conn) {
+        Ok(mode) if mode.eq_ignore_ascii_case("wal") => println!("SQLite journal mode: {}", mode),
+        Ok(mode) => eprintln!("SQLite did not enable WAL; journal mode is {}", mode),
+        Err(e) => eprintln!("Failed to configure the SQLite connection: {}", e),
+    }
+
+    // Holds `schema_version`, so it must exist before migrating
+    create_config_table(&conn)
+        .map_err(|e| DbInitError::Database(format!("Failed to read the database {}: {}", db_path.display(), e)))?;
+
+    // Migrations alter existing data, so keep a copy to roll back to
+    let version = schema_version(&conn)
+        .map_err(|e| DbInitError::Database(format!("Failed to read the schema version: {}", e)))?;
+    let backup_path = if existed && version < MIGRATIONS.len() as i64 {
+        // In WAL mode recent commits may still sit in the -wal file; fold them into the
+        // database file so the copy is complete
+        if let Err(e) = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())) {
+            eprintln!("Failed to checkpoint the database before backup: {}", e);
+        }
+        Some(backup_db_file(&db_path).map_err(DbInitError::Database)?)
+    } else {
+        None
+    };
+
+    if let Err(e) = run_migrations(&conn) {
+        // Don't leave a half-migrated database behind
+        drop(conn);
+        let mut message = format!("Failed to upgrade the database schema: {}", e);
+        if let Some(backup_path) = &backup_path {
+            match fs::copy(backup_path, &db_path) {
+                Ok(_) => message.push_str(". The database was restored from the backup."),
+                Err(restore_err) => message.push_str(&format!(
+                    ". Restoring the backup {} failed: {}",
+                    backup_path.display(),
+                    restore_err
+                )),
+            }
         }
+        return Err(DbInitError::Database(message));
+    }
+
+    // Search falls back to LIKE queries when this fails
+    if let Err(e) = create_message_search_index(&conn) {
+        eprintln!("Full-text search is unavailable: {}", e);
     }
 
-    let conn = Connection::open(db_path).expect("Failed to open SQLite database");
+    // Schema work is done; every later connection only needs the per-connection settings
+    drop(conn);
+    r2d2::Pool::builder()
+        .max_size(POOL_SIZE)
+        .build(SqliteConnectionManager::file(&db_path).with_init(|conn| configure_connection(conn).map(|_| ())))
+        .map_err(|e| DbInitError::Database(format!("Failed to open the database pool: {}", e)))
+}
+
+// Ordered schema migrations. Applying step N brings the database to `schema_version` N + 1.
+// Steps must be idempotent, since databases from before versioning start at version 0
+// whatever tables they already have. Append new steps; never edit released ones.
+const MIGRATIONS: &[(&str, fn(&Connection) -> Result<()>)] = &[
+    ("create base tables", create_base_tables),
+    ("add session settings and message state columns", add_session_and_message_columns),
+    ("date sessions by their last message", backfill_session_activity),
+    ("add session pinning", add_session_pinned_column),
+    ("add session archiving", add_session_archived_column),
+    ("add session folders", add_session_folders),
+    ("store reasoning separately from replies", add_message_thinking_column),
+    ("record the seed of each reply", add_message_stats_seed_column),
+    ("add per-session context budget", add_session_context_tokens_column),
+    ("add message images", create_message_images_table),
+    ("add file attachments", create_attachments_table),
+    ("store chat timestamps as RFC 3339 UTC", convert_timestamps_to_rfc3339),
+    ("record the sampling settings of each reply", add_message_stats_sampling_columns),
+    ("add message favorites", add_message_favorite_column),
+    ("add prompt templates", create_prompt_templates_table),
+    ("store the remaining timestamps as RFC 3339 UTC", convert_remaining_timestamps_to_rfc3339),
+];
 
+fn create_config_table(conn: &Connection) -> Result<()> {
     conn.execute(
         "CREATE TABLE IF NOT EXISTS app_config (
             key TEXT PRIMARY KEY,
             value TEXT
         )",
         [],
-    ).expect("Failed to create app_config table");
+    )?;
+    Ok(())
+}
+
+/// Returns the schema version recorded in `app_config`, 0 for an unversioned database.
+pub fn schema_version(conn: &Connection) -> Result<i64> {
+    Ok(get_config_value(conn, "schema_version")?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0))
+}
+
+/// Applies every migration newer than the database's `schema_version`, each in its own transaction.
+pub fn run_migrations(conn: &Connection) -> Result<()> {
+    let version = schema_version(conn)?;
+    for (index, (name, migrate)) in MIGRATIONS.iter().enumerate() {
+        let target = index as i64 + 1;
+        if target <= version {
+            continue;
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        migrate(&tx)?;
+        update_config_value(&tx, "schema_version", &target.to_string())?;
+        tx.commit()?;
+        println!("Applied database migration {}: {}", target, name);
+    }
+    Ok(())
+}
+
+/// Opens an in-memory database with the full schema, for tests.
+#[cfg(test)]
+pub fn open_test_db() -> Connection {
+    let conn = Connection::open_in_memory().expect("failed to open an in-memory database");
+    create_config_table(&conn).expect("failed to create app_config");
+    run_migrations(&conn).expect("failed to migrate the test database");
+    conn
+}
 
+fn create_base_tables(conn: &Connection) -> Result<()> {
     // Chat sessions table
     conn.execute(
         "CREATE TABLE IF NOT EXISTS chat_sessions (
@@ -68,7 +655,7 @@ pub fn init_db() -> Arc<Mutex<Connection>> {
             created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
         )",
         [],
-    ).expect("Failed to create chat_sessions table");
+    )?;
     
     // Chat history table
     conn.execute(
@@ -81,100 +668,770 @@ pub fn init_db() -> Arc<Mutex<Connection>> {
             FOREIGN KEY (session_id) REFERENCES chat_sessions(id)
         )",
         [],
-    ).expect("Failed to create chat_history table");
+    )?;
 
-    Arc::new(Mutex::new(conn))
-}
+    // Generation statistics for assistant messages
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_stats (
+            message_id INTEGER PRIMARY KEY,
+            prompt_eval_count INTEGER,
+            eval_count INTEGER,
+            prompt_eval_duration INTEGER,
+            eval_duration INTEGER,
+            total_duration INTEGER,
+            FOREIGN KEY (message_id) REFERENCES chat_history(id)
+        )",
+        [],
+    )?;
 
-/// Inserts or updates a configuration key-value pair.
-pub fn update_config_value(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
-    //println!("Updating config value: key = {}, value = {}", 
-    //    key, value);
+    // Reusable persona presets
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS personas (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            label TEXT NOT NULL,
+            model TEXT,
+            system_prompt TEXT,
+            options TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
 
+    // Log of session and model actions
     conn.execute(
-        "INSERT OR REPLACE INTO app_config (key, value) VALUES (?1, ?2)",
-        [key, value],
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            action TEXT NOT NULL,
+            target_id INTEGER,
+            detail TEXT,
+            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    // Embedding of each session's text for semantic search. `last_message_id` is the
+    // newest message included, so stale embeddings can be detected.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS session_embeddings (
+            session_id INTEGER PRIMARY KEY,
+            model TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            last_message_id INTEGER NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (session_id) REFERENCES chat_sessions(id)
+        )",
+        [],
     )?;
+
     Ok(())
 }
 
-/// Retrieves a configuration value by key. Returns `None` if the key doesn't exist.
-pub fn get_config_value(conn: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
-    conn.query_row(
-        "SELECT value FROM app_config WHERE key = ?1",
-        [key],
-        |row| row.get(0),
-    ).optional()
+/// Copies the live database to `dest` with SQLite's online backup API, which is safe while
+/// other connections keep reading and writing.
+pub fn backup_database(conn: &Connection, dest: &Path) -> std::result::Result<(), String> {
+    if dest == db_path() {
+        return Err("Choose a different file than the live database".to_string());
+    }
+    conn.backup(DatabaseName::Main, dest, None)
+        .map_err(|e| format!("Failed to back up the database to {}: {}", dest.display(), e))
 }
 
-pub async fn remove_chat_session(
-    session_id: i64,
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
-    state: State<'_, Arc<Mutex<GenerationState>>>,
-) -> Result<(), rusqlite::Error> {
-    let conn = db.lock().await;
-    conn.execute(
-        "DELETE FROM chat_sessions WHERE id = ?1",
-        params![session_id],
-    )?;
+// Check that `src` is an intact chat database whose schema this version can migrate
+fn validate_backup_file(src: &Path) -> std::result::Result<(), String> {
+    let invalid = |e: rusqlite::Error| format!("{} is not a valid database: {}", src.display(), e);
+    let src_conn = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(invalid)?;
 
-    // Check if the deleted session is the current session
-    let mut generation_state = state.lock().await;
-    if generation_state.current_session_id == Some(session_id) {
-        generation_state.current_session_id = Some(-1); // Clear the current session
+    let check: String = src_conn.query_row("PRAGMA quick_check", [], |row| row.get(0)).map_err(invalid)?;
+    if check != "ok" {
+        return Err(format!("{} is damaged: {}", src.display(), check));
+    }
+    for table in ["chat_sessions", "chat_history"] {
+        if !table_exists(&src_conn, table).map_err(invalid)? {
+            return Err(format!("{} is not an OllamaChat database: it has no {} table", src.display(), table));
+        }
+    }
+    let version = if table_exists(&src_conn, "app_config").map_err(invalid)? {
+        schema_version(&src_conn).map_err(invalid)?
+    } else {
+        0
+    };
+    if version > MIGRATIONS.len() as i64 {
+        return Err(format!(
+            "{} was made by a newer version of OllamaChat (schema {}, this version supports up to {})",
+            src.display(),
+            version,
+            MIGRATIONS.len()
+        ));
     }
-
     Ok(())
 }
 
-pub async fn fetch_current_session(
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
-    state: State<'_, Arc<Mutex<GenerationState>>>,
-) -> Result<CurrentSession, rusqlite::Error> {
-    let gen_state = state.lock().await;
+/// Replaces the live database with the backup at `src` once it has been validated, keeping a
+/// `.bak` copy of the current database. Migrations then bring an older backup up to date.
+pub fn restore_database(conn: &mut Connection, src: &Path) -> std::result::Result<(), String> {
+    validate_backup_file(src)?;
 
-    match gen_state.current_session_id {
-        Some(id) if id != -1 => {
-            let conn = db.lock().await;
-            let mut stmt = conn.prepare("SELECT title FROM chat_sessions WHERE id = ?1")?;
+    // Fold the WAL into the file so the safety copy is complete
+    if let Err(e) = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())) {
+        eprintln!("Failed to checkpoint the database before restore: {}", e);
+    }
+    backup_db_file(&db_path())?;
 
-            let title: Option<String> = stmt.query_row(params![id], |row| row.get(0)).optional()?;
+    conn.restore(DatabaseName::Main, src, None::<fn(rusqlite::backup::Progress)>)
+        .map_err(|e| format!("Failed to restore the database from {}: {}", src.display(), e))?;
 
-            Ok(CurrentSession {
-                id,
-                title: title.unwrap_or_default(),
-            })
-        }
-        _ => Ok(CurrentSession {
-            id: -1,
-            title: String::new(),
-        }),
+    create_config_table(conn).and_then(|_| run_migrations(conn))
+        .map_err(|e| format!("Failed to upgrade the restored database: {}", e))?;
+    if let Err(e) = create_message_search_index(conn) {
+        eprintln!("Full-text search is unavailable: {}", e);
     }
+    Ok(())
 }
 
-pub async fn rename_chat_session(
-    session_id: i64,
-    new_name: String,
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
-) -> Result<(), rusqlite::Error> {
-    let conn = db.lock().await;
-    conn.execute(
-        "UPDATE chat_sessions SET title = ?1 WHERE id = ?2",
-        params![new_name, session_id],
+fn table_exists(conn: &Connection, name: &str) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = ?1)",
+        params![name],
+        |row| row.get(0),
+    )
+}
+
+// Mirror chat_history.message into an FTS5 index, kept in sync by triggers.
+// Fails when SQLite was built without FTS5.
+fn create_message_search_index(conn: &Connection) -> Result<()> {
+    let existed = table_exists(conn, "chat_history_fts")?;
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS chat_history_fts
+            USING fts5(message, content='chat_history', content_rowid='id');
+        CREATE TRIGGER IF NOT EXISTS chat_history_fts_insert AFTER INSERT ON chat_history BEGIN
+            INSERT INTO chat_history_fts(rowid, message) VALUES (new.id, new.message);
+        END;
+        CREATE TRIGGER IF NOT EXISTS chat_history_fts_delete AFTER DELETE ON chat_history BEGIN
+            INSERT INTO chat_history_fts(chat_history_fts, rowid, message) VALUES ('delete', old.id, old.message);
+        END;
+        CREATE TRIGGER IF NOT EXISTS chat_history_fts_update AFTER UPDATE OF message ON chat_history BEGIN
+            INSERT INTO chat_history_fts(chat_history_fts, rowid, message) VALUES ('delete', old.id, old.message);
+            INSERT INTO chat_history_fts(rowid, message) VALUES (new.id, new.message);
+        END;",
     )?;
 
+    // Index the messages written before the index existed
+    if !existed {
+        conn.execute("INSERT INTO chat_history_fts(chat_history_fts) VALUES ('rebuild')", [])?;
+    }
     Ok(())
 }
 
-pub async fn fetch_chat_sessions(
-    db: State<'_, Arc<Mutex<rusqlite::Connection>>>,
-) -> Result<Vec<ChatSession>, rusqlite::Error> {
-    let conn = db.lock().await;
-    let mut stmt = conn.prepare("SELECT id, title FROM chat_sessions ORDER BY id DESC")?;
-    let sessions_iter = stmt.query_map([], |row| {
-        Ok(ChatSession {
-            id: row.get(0)?,
+// Add a column to an existing table unless it is already there
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>>>()?;
+
+    if !columns.iter().any(|name| name == column) {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn add_session_and_message_columns(conn: &Connection) -> Result<()> {
+    // Per-session override of the global `show_thinking` setting (NULL = use global)
+    ensure_column(conn, "chat_sessions", "show_thinking", "INTEGER")?;
+    // Model that generated an assistant message
+    ensure_column(conn, "chat_history", "model", "TEXT")?;
+    // Model a session is pinned to, and the digest it resolved to when pinned
+    ensure_column(conn, "chat_sessions", "model", "TEXT")?;
+    ensure_column(conn, "chat_sessions", "model_digest", "TEXT")?;
+    // User's private note on a message, independent of its content
+    ensure_column(conn, "chat_history", "annotation", "TEXT")?;
+    // System prompt and generation options (JSON) applied to every request in a session
+    ensure_column(conn, "chat_sessions", "system_prompt", "TEXT")?;
+    ensure_column(conn, "chat_sessions", "options", "TEXT")?;
+    // Set when generation of an assistant message was aborted
+    ensure_column(conn, "chat_history", "stopped", "INTEGER NOT NULL DEFAULT 0")?;
+    // Time of the last message added to a session
+    ensure_column(conn, "chat_sessions", "updated_at", "TIMESTAMP")?;
+    conn.execute("UPDATE chat_sessions SET updated_at = created_at WHERE updated_at IS NULL", [])?;
+    // Set when a reply was cut off by the `num_predict` length cap
+    ensure_column(conn, "chat_history", "truncated", "INTEGER NOT NULL DEFAULT 0")?;
+
+    Ok(())
+}
+
+fn backfill_session_activity(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET updated_at = COALESCE(
+            (SELECT MAX(h.timestamp) FROM chat_history h WHERE h.session_id = chat_sessions.id),
+            created_at
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_session_pinned_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "chat_sessions", "pinned", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn add_session_archived_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "chat_sessions", "archived", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn add_session_folders(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    ensure_column(conn, "chat_sessions", "folder_id", "INTEGER REFERENCES folders(id)")
+}
+
+fn add_message_thinking_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "chat_history", "thinking", "TEXT")
+}
+
+fn add_message_stats_seed_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "message_stats", "seed", "INTEGER")
+}
+
+fn add_session_context_tokens_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "chat_sessions", "context_tokens", "INTEGER")
+}
+
+// Images live apart from chat_history so loading message text stays cheap
+fn create_message_images_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_images (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL,
+            data TEXT NOT NULL,
+            FOREIGN KEY (message_id) REFERENCES chat_history(id)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS message_images_message_id ON message_images (message_id)",
+        [],
+    )?;
+    Ok(())
+}
+
+// Attachments wait with a NULL message_id until the next prompt of their session picks them up
+fn create_attachments_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id INTEGER NOT NULL,
+            message_id INTEGER,
+            file_name TEXT NOT NULL,
+            content TEXT NOT NULL,
+            byte_count INTEGER NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (session_id) REFERENCES chat_sessions(id),
+            FOREIGN KEY (message_id) REFERENCES chat_history(id)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn create_prompt_templates_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS prompt_templates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_message_favorite_column(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "chat_history", "is_favorite", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn add_message_stats_sampling_columns(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "message_stats", "temperature", "REAL")?;
+    ensure_column(conn, "message_stats", "num_ctx", "INTEGER")
+}
+
+// CURRENT_TIMESTAMP is UTC but has no zone marker, so clients read it as local time.
+// strftime() accepts both forms, which keeps this step idempotent; unparseable values are left alone.
+fn convert_timestamps_to_rfc3339(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "UPDATE chat_history SET timestamp = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', timestamp), timestamp);
+        UPDATE chat_sessions SET
+            created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', created_at), created_at),
+            updated_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', updated_at), updated_at);",
+    )
+}
+
+// The other tables written with CURRENT_TIMESTAMP, converted the same way
+fn convert_remaining_timestamps_to_rfc3339(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "UPDATE audit_log SET timestamp = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', timestamp), timestamp);
+        UPDATE session_embeddings SET updated_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', updated_at), updated_at);
+        UPDATE folders SET created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', created_at), created_at);
+        UPDATE personas SET created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', created_at), created_at);
+        UPDATE attachments SET created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', created_at), created_at);",
+    )
+}
+
+/// Inserts or updates a configuration key-value pair.
+pub fn update_config_value(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
+    //println!("Updating config value: key = {}, value = {}", 
+    //    key, value);
+
+    conn.execute(
+        "INSERT OR REPLACE INTO app_config (key, value) VALUES (?1, ?2)",
+        [key, value],
+    )?;
+    Ok(())
+}
+
+/// Retrieves a configuration value by key. Returns `None` if the key doesn't exist.
+pub fn get_config_value(conn: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT value FROM app_config WHERE key = ?1",
+        [key],
+        |row| row.get(0),
+    ).optional()
+}
+
+/// Returns every key stored in app_config.
+pub fn fetch_config_keys(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT key FROM app_config ORDER BY key")?;
+    let keys = stmt.query_map([], |row| row.get(0))?;
+    keys.collect()
+}
+
+/// Returns whether reasoning output should be shown for a session,
+/// resolving the session override first and then the global `show_thinking` default.
+pub fn resolve_show_thinking(conn: &Connection, session_id: i64) -> Result<bool> {
+    let session_override: Option<bool> = conn
+        .query_row(
+            "SELECT show_thinking FROM chat_sessions WHERE id = ?1",
+            params![session_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+
+    if let Some(show) = session_override {
+        return Ok(show);
+    }
+
+    Ok(get_config_value(conn, "show_thinking")?
+        .map(|value| value == "true")
+        .unwrap_or(true))
+}
+
+/// Sets or clears (`None`) the per-session `show_thinking` override.
+pub fn set_session_show_thinking(conn: &Connection, session_id: i64, show: Option<bool>) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET show_thinking = ?1 WHERE id = ?2",
+        params![show, session_id],
+    )?;
+    Ok(())
+}
+
+/// Removes a configuration key.
+pub fn delete_config_value(conn: &Connection, key: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM app_config WHERE key = ?1", [key])?;
+    Ok(())
+}
+
+/// Returns the model and digest a session is pinned to, if any.
+pub fn get_session_model_pin(conn: &Connection, session_id: i64) -> Result<Option<(String, String)>> {
+    let pin: Option<(Option<String>, Option<String>)> = conn
+        .query_row(
+            "SELECT model, model_digest FROM chat_sessions WHERE id = ?1",
+            params![session_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    Ok(match pin {
+        Some((Some(model), Some(digest))) => Some((model, digest)),
+        _ => None,
+    })
+}
+
+/// Pins a session to a model and the digest it currently resolves to.
+pub fn set_session_model_pin(conn: &Connection, session_id: i64, model: &str, digest: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET model = ?1, model_digest = ?2 WHERE id = ?3",
+        params![model, digest, session_id],
+    )?;
+    Ok(())
+}
+
+/// Sets or clears (with `None`) the system prompt sent with every request in a session.
+pub fn set_session_system_prompt(conn: &Connection, session_id: i64, prompt: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET system_prompt = ?1 WHERE id = ?2",
+        params![prompt, session_id],
+    )?;
+    Ok(())
+}
+
+/// Sets or clears (with `None`) the token budget for the history sent with a session's requests.
+pub fn set_session_context_tokens(conn: &Connection, session_id: i64, max_tokens: Option<i64>) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET context_tokens = ?1 WHERE id = ?2",
+        params![max_tokens, session_id],
+    )?;
+    Ok(())
+}
+
+/// Stores the generation options a session uses, e.g. the last ones sent with a request.
+pub fn set_session_options(conn: &Connection, session_id: i64, options: &Option<GenerationOptions>) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET options = ?1 WHERE id = ?2",
+        params![options_to_json(options), session_id],
+    )?;
+    Ok(())
+}
+
+/// Sets the model a session uses without pinning a digest.
+pub fn set_session_model(conn: &Connection, session_id: i64, model: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET model = ?1, model_digest = NULL WHERE id = ?2",
+        params![model, session_id],
+    )?;
+    Ok(())
+}
+
+/// Records the model a session was started with, leaving a model that is already set alone.
+pub fn init_session_model(conn: &Connection, session_id: i64, model: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET model = ?1 WHERE id = ?2 AND model IS NULL",
+        params![model, session_id],
+    )?;
+    Ok(())
+}
+
+/// Returns the generation options used by sessions that don't set their own.
+pub fn get_default_options(conn: &Connection) -> Option<GenerationOptions> {
+    options_from_json(get_config_value(conn, "default_options").ok().flatten())
+}
+
+pub(crate) fn options_to_json(options: &Option<GenerationOptions>) -> Option<String> {
+    options.as_ref().and_then(|o| serde_json::to_string(o).ok())
+}
+
+fn options_from_json(json: Option<String>) -> Option<GenerationOptions> {
+    json.and_then(|j| serde_json::from_str(&j).ok())
+}
+
+/// Returns the generation settings stored on a session.
+pub fn get_session_config(conn: &Connection, session_id: i64) -> Result<SessionConfig> {
+    conn.query_row(
+        "SELECT model, system_prompt, options, show_thinking, context_tokens FROM chat_sessions WHERE id = ?1",
+        params![session_id],
+        |row| {
+            Ok(SessionConfig {
+                session_id,
+                model: row.get(0)?,
+                system_prompt: row.get(1)?,
+                options: options_from_json(row.get(2)?),
+                show_thinking: row.get(3)?,
+                context_tokens: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+    .map(|config| config.unwrap_or(SessionConfig { session_id, ..Default::default() }))
+}
+
+pub fn create_persona(conn: &Connection, persona: &PersonaInput) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO personas (label, model, system_prompt, options, created_at)
+         VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![persona.label, persona.model, persona.system_prompt, options_to_json(&persona.options)],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn update_persona(conn: &Connection, persona_id: i64, persona: &PersonaInput) -> Result<()> {
+    conn.execute(
+        "UPDATE personas SET label = ?1, model = ?2, system_prompt = ?3, options = ?4 WHERE id = ?5",
+        params![persona.label, persona.model, persona.system_prompt, options_to_json(&persona.options), persona_id],
+    )?;
+    Ok(())
+}
+
+pub fn delete_persona(conn: &Connection, persona_id: i64) -> Result<()> {
+    conn.execute("DELETE FROM personas WHERE id = ?1", params![persona_id])?;
+    Ok(())
+}
+
+pub fn fetch_personas(conn: &Connection) -> Result<Vec<Persona>> {
+    let mut stmt = conn.prepare("SELECT id, label, model, system_prompt, options FROM personas ORDER BY label ASC")?;
+    let personas_iter = stmt.query_map([], |row| {
+        Ok(Persona {
+            id: row.get(0)?,
+            label: row.get(1)?,
+            model: row.get(2)?,
+            system_prompt: row.get(3)?,
+            options: options_from_json(row.get(4)?),
+        })
+    })?;
+
+    let mut personas = Vec::new();
+    for persona in personas_iter {
+        personas.push(persona?);
+    }
+
+    Ok(personas)
+}
+
+// Names of the `{{name}}` placeholders in a template, without duplicates
+fn template_variables(content: &str) -> Vec<String> {
+    let re = regex::Regex::new(r"\{\{\s*([^{}]+?)\s*\}\}").unwrap();
+    let mut variables: Vec<String> = Vec::new();
+    for capture in re.captures_iter(content) {
+        let name = capture[1].to_string();
+        if !variables.contains(&name) {
+            variables.push(name);
+        }
+    }
+    variables
+}
+
+fn prompt_template_from_row(row: &rusqlite::Row) -> Result<PromptTemplate> {
+    let content: String = row.get(2)?;
+    Ok(PromptTemplate {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        variables: template_variables(&content),
+        content,
+        created_at: row.get(3)?,
+    })
+}
+
+pub fn create_prompt_template(conn: &Connection, name: &str, content: &str) -> Result<PromptTemplate> {
+    conn.execute(
+        "INSERT INTO prompt_templates (name, content, created_at) VALUES (?1, ?2, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![name, content],
+    )?;
+    get_prompt_template(conn, conn.last_insert_rowid())
+}
+
+/// Replaces a template's name and content; fails with `QueryReturnedNoRows` for an unknown id.
+pub fn update_prompt_template(conn: &Connection, template_id: i64, name: &str, content: &str) -> Result<PromptTemplate> {
+    let updated = conn.execute(
+        "UPDATE prompt_templates SET name = ?1, content = ?2 WHERE id = ?3",
+        params![name, content, template_id],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    get_prompt_template(conn, template_id)
+}
+
+pub fn delete_prompt_template(conn: &Connection, template_id: i64) -> Result<()> {
+    conn.execute("DELETE FROM prompt_templates WHERE id = ?1", params![template_id])?;
+    Ok(())
+}
+
+pub fn get_prompt_template(conn: &Connection, template_id: i64) -> Result<PromptTemplate> {
+    conn.query_row(
+        "SELECT id, name, content, created_at FROM prompt_templates WHERE id = ?1",
+        params![template_id],
+        prompt_template_from_row,
+    )
+}
+
+pub fn fetch_prompt_templates(conn: &Connection) -> Result<Vec<PromptTemplate>> {
+    let mut stmt = conn.prepare("SELECT id, name, content, created_at FROM prompt_templates ORDER BY name ASC")?;
+    let templates = stmt.query_map([], prompt_template_from_row)?;
+    templates.collect()
+}
+
+/// Copies a persona's model, system prompt and options onto a session.
+/// Any model digest pin is cleared since it belonged to the previous model.
+pub fn apply_persona(conn: &Connection, session_id: i64, persona_id: i64) -> Result<SessionConfig> {
+    let (model, system_prompt, options): (Option<String>, Option<String>, Option<String>) = conn.query_row(
+        "SELECT model, system_prompt, options FROM personas WHERE id = ?1",
+        params![persona_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    let updated = conn.execute(
+        "UPDATE chat_sessions SET model = ?1, system_prompt = ?2, options = ?3, model_digest = NULL WHERE id = ?4",
+        params![model, system_prompt, options, session_id],
+    )?;
+
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+
+    get_session_config(conn, session_id)
+}
+
+pub async fn remove_chat_session(
+    session_id: i64,
+    db: State<'_, DbPool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+) -> Result<(), rusqlite::Error> {
+    let conn = checkout(&db)?;
+    conn.execute(
+        "DELETE FROM chat_sessions WHERE id = ?1",
+        params![session_id],
+    )?;
+    record_audit(&conn, AUDIT_SESSION_DELETED, Some(session_id), None)?;
+
+    // Check if the deleted session is the current session
+    let mut generation_state = state.lock().await;
+    if generation_state.current_session_id == Some(session_id) {
+        generation_state.current_session_id = Some(-1); // Clear the current session
+    }
+
+    Ok(())
+}
+
+/// Returns a session's title, or `None` if the session doesn't exist.
+pub fn get_session_title(conn: &Connection, session_id: i64) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT title FROM chat_sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Config key holding the session that was open when the app last ran.
+pub const LAST_SESSION_KEY: &str = "last_session_id";
+
+/// Returns the saved last active session, or -1 if none was saved or it has since been deleted.
+pub fn last_session_id(conn: &Connection) -> Result<i64> {
+    let Some(id) = get_config_value(conn, LAST_SESSION_KEY)?.and_then(|v| v.parse::<i64>().ok()) else {
+        return Ok(-1);
+    };
+    Ok(if get_session_title(conn, id)?.is_some() { id } else { -1 })
+}
+
+/// Remembers `session_id` as the session to reopen on the next launch; `None` forgets it.
+pub fn set_last_session(conn: &Connection, session_id: Option<i64>) -> Result<()> {
+    match session_id {
+        Some(id) => update_config_value(conn, LAST_SESSION_KEY, &id.to_string()),
+        None => delete_config_value(conn, LAST_SESSION_KEY),
+    }
+}
+
+pub async fn fetch_current_session(
+    db: State<'_, DbPool>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+) -> Result<CurrentSession, rusqlite::Error> {
+    let gen_state = state.lock().await;
+
+    match gen_state.current_session_id {
+        Some(id) if id != -1 => {
+            let conn = checkout(&db)?;
+            let mut stmt = conn.prepare("SELECT title FROM chat_sessions WHERE id = ?1")?;
+
+            let title: Option<String> = stmt.query_row(params![id], |row| row.get(0)).optional()?;
+
+            Ok(CurrentSession {
+                id,
+                title: title.unwrap_or_default(),
+            })
+        }
+        _ => Ok(CurrentSession {
+            id: -1,
+            title: String::new(),
+        }),
+    }
+}
+
+pub async fn rename_chat_session(
+    session_id: i64,
+    new_name: String,
+    db: State<'_, DbPool>,
+) -> Result<(), rusqlite::Error> {
+    let conn = checkout(&db)?;
+    set_session_title(&conn, session_id, &new_name)
+}
+
+/// Deletes every session, message and the data derived from them in one transaction.
+/// Settings in `app_config`, personas and prompt templates survive unless `include_config` is set;
+/// `schema_version` is always kept. The file is vacuumed so deleted text doesn't linger on disk.
+pub fn clear_all_data(conn: &mut Connection, include_config: bool) -> Result<ClearedData> {
+    let tx = conn.transaction()?;
+    for table in ["message_stats", "message_images", "attachments", "session_embeddings"] {
+        tx.execute(&format!("DELETE FROM {}", table), [])?;
+    }
+    // The chat_history triggers keep the search index in step
+    let messages = tx.execute("DELETE FROM chat_history", [])?;
+    let sessions = tx.execute("DELETE FROM chat_sessions", [])?;
+    tx.execute("DELETE FROM folders", [])?;
+    tx.execute("DELETE FROM audit_log", [])?;
+    if include_config {
+        tx.execute("DELETE FROM personas", [])?;
+        tx.execute("DELETE FROM prompt_templates", [])?;
+        tx.execute("DELETE FROM app_config WHERE key <> 'schema_version'", [])?;
+    }
+    tx.commit()?;
+
+    conn.execute_batch("VACUUM")?;
+    Ok(ClearedData { sessions, messages })
+}
+
+/// Changes a session's title and records the rename in the audit log.
+pub fn set_session_title(conn: &Connection, session_id: i64, title: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET title = ?1 WHERE id = ?2",
+        params![title, session_id],
+    )?;
+    record_audit(conn, AUDIT_SESSION_RENAMED, Some(session_id), Some(title))?;
+
+    Ok(())
+}
+
+/// Returns the first user message of a session and the first reply after it.
+pub fn fetch_first_exchange(conn: &Connection, session_id: i64) -> Result<(Option<String>, Option<String>)> {
+    let first = |sql: &str| -> Result<Option<String>> {
+        conn.query_row(sql, params![session_id], |row| row.get(0)).optional()
+    };
+    let prompt = first("SELECT message FROM chat_history WHERE session_id = ?1 AND role = 'user' ORDER BY id ASC LIMIT 1")?;
+    let reply = first(
+        "SELECT message FROM chat_history WHERE session_id = ?1 AND role = 'assistant' AND id >
+            (SELECT MIN(id) FROM chat_history WHERE session_id = ?1 AND role = 'user')
+         ORDER BY id ASC LIMIT 1",
+    )?;
+    Ok((prompt, reply))
+}
+
+pub async fn fetch_chat_sessions(
+    include_archived: bool,
+    db: State<'_, DbPool>,
+) -> Result<Vec<ChatSession>, rusqlite::Error> {
+    let conn = checkout(&db)?;
+    // Pinned first, then most recently active; `id` breaks ties between sessions touched in the same second
+    let mut stmt = conn.prepare(
+        "SELECT id, title, pinned, archived, folder_id, model FROM chat_sessions
+         WHERE ?1 OR archived = 0
+         ORDER BY pinned DESC, updated_at DESC, id DESC",
+    )?;
+    let sessions_iter = stmt.query_map(params![include_archived], |row| {
+        Ok(ChatSession {
+            id: row.get(0)?,
             title: row.get(1)?,
+            pinned: row.get(2)?,
+            archived: row.get(3)?,
+            folder_id: row.get(4)?,
+            model: row.get(5)?,
         })
     })?;
 
@@ -183,11 +1440,239 @@ pub async fn fetch_chat_sessions(
         sessions.push(session?);
     }
 
-    Ok(sessions)
+    Ok(sessions)
+}
+
+/// Lists sessions with at least one message generated by `model`.
+pub async fn fetch_sessions_using_model(
+    model: &str,
+    db: State<'_, DbPool>,
+) -> Result<Vec<ChatSession>, rusqlite::Error> {
+    let conn = checkout(&db)?;
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT s.id, s.title, s.pinned, s.archived, s.folder_id, s.model FROM chat_sessions s
+         JOIN chat_history h ON h.session_id = s.id
+         WHERE h.model = ?1
+         ORDER BY s.id DESC",
+    )?;
+    let sessions_iter = stmt.query_map(params![model], |row| {
+        Ok(ChatSession {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            pinned: row.get(2)?,
+            archived: row.get(3)?,
+            folder_id: row.get(4)?,
+            model: row.get(5)?,
+        })
+    })?;
+
+    let mut sessions = Vec::new();
+    for session in sessions_iter {
+        sessions.push(session?);
+    }
+
+    Ok(sessions)
+}
+
+/// Pins or unpins a session; `None` flips the current state. Returns the new state.
+pub fn set_session_pinned(conn: &Connection, session_id: i64, pinned: Option<bool>) -> Result<bool> {
+    let pinned = match pinned {
+        Some(pinned) => pinned,
+        None => !conn.query_row(
+            "SELECT pinned FROM chat_sessions WHERE id = ?1",
+            params![session_id],
+            |row| row.get::<_, bool>(0),
+        )?,
+    };
+    let updated = conn.execute(
+        "UPDATE chat_sessions SET pinned = ?1 WHERE id = ?2",
+        params![pinned, session_id],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(pinned)
+}
+
+/// Archives or restores a session.
+pub fn set_session_archived(conn: &Connection, session_id: i64, archived: bool) -> Result<()> {
+    let updated = conn.execute(
+        "UPDATE chat_sessions SET archived = ?1 WHERE id = ?2",
+        params![archived, session_id],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+/// Counts active and archived sessions.
+pub fn count_sessions(conn: &Connection) -> Result<SessionCounts> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(archived = 0), 0), COALESCE(SUM(archived != 0), 0) FROM chat_sessions",
+        [],
+        |row| Ok(SessionCounts { active: row.get(0)?, archived: row.get(1)? }),
+    )
+}
+
+/// Creates a folder and returns its id.
+pub fn create_folder(conn: &Connection, name: &str) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO folders (name, created_at) VALUES (?1, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![name],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn rename_folder(conn: &Connection, folder_id: i64, name: &str) -> Result<()> {
+    let updated = conn.execute(
+        "UPDATE folders SET name = ?1 WHERE id = ?2",
+        params![name, folder_id],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+/// Deletes a folder, moving its sessions out of it rather than deleting them.
+pub fn delete_folder(conn: &mut Connection, folder_id: i64) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("UPDATE chat_sessions SET folder_id = NULL WHERE folder_id = ?1", params![folder_id])?;
+    tx.execute("DELETE FROM folders WHERE id = ?1", params![folder_id])?;
+    tx.commit()
+}
+
+/// Lists all folders by name.
+pub fn fetch_folders(conn: &Connection) -> Result<Vec<Folder>> {
+    let mut stmt = conn.prepare("SELECT id, name, created_at FROM folders ORDER BY name COLLATE NOCASE")?;
+    let folders = stmt.query_map([], |row| {
+        Ok(Folder {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            created_at: row.get(2)?,
+        })
+    })?;
+    folders.collect()
+}
+
+/// Puts a session into a folder, or takes it out of any folder with `None`.
+pub fn move_session_to_folder(conn: &Connection, session_id: i64, folder_id: Option<i64>) -> Result<()> {
+    if let Some(folder_id) = folder_id {
+        // Don't point a session at a folder that doesn't exist
+        conn.query_row("SELECT id FROM folders WHERE id = ?1", params![folder_id], |row| row.get::<_, i64>(0))?;
+    }
+    let updated = conn.execute(
+        "UPDATE chat_sessions SET folder_id = ?1 WHERE id = ?2",
+        params![folder_id, session_id],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(())
+}
+
+/// Creates a new session with the given title and returns its id.
+pub fn create_session(conn: &Connection, title: &str) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO chat_sessions (title, created_at, updated_at)
+         VALUES (?1, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![title],
+    )?;
+    let session_id = conn.last_insert_rowid();
+    record_audit(conn, AUDIT_SESSION_CREATED, Some(session_id), Some(title))?;
+    Ok(session_id)
+}
+
+/// Copies a session's settings and its messages up to and including `up_to_message_id` into a
+/// new session titled "<title> (copy)", with each message's stats, images and attachments.
+/// Returns the new session's id.
+pub fn fork_session(conn: &mut Connection, session_id: i64, up_to_message_id: i64) -> Result<i64> {
+    let tx = conn.transaction()?;
+
+    // Fail with QueryReturnedNoRows unless the message belongs to the session
+    tx.query_row(
+        "SELECT id FROM chat_history WHERE id = ?1 AND session_id = ?2",
+        params![up_to_message_id, session_id],
+        |row| row.get::<_, i64>(0),
+    )?;
+
+    let title: String = tx.query_row(
+        "SELECT title FROM chat_sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get(0),
+    )?;
+    let title = format!("{} (copy)", title);
+    tx.execute(
+        "INSERT INTO chat_sessions (title, created_at, updated_at, model, model_digest, system_prompt, options,
+             show_thinking, context_tokens, folder_id)
+         SELECT ?1, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), model, model_digest,
+             system_prompt, options, show_thinking, context_tokens, folder_id
+         FROM chat_sessions WHERE id = ?2",
+        params![title, session_id],
+    )?;
+    let fork_id = tx.last_insert_rowid();
+
+    let message_ids: Vec<i64> = {
+        let mut stmt = tx.prepare("SELECT id FROM chat_history WHERE session_id = ?1 AND id <= ?2 ORDER BY id ASC")?;
+        let ids = stmt.query_map(params![session_id, up_to_message_id], |row| row.get(0))?;
+        ids.collect::<Result<_>>()?
+    };
+    for message_id in message_ids {
+        tx.execute(
+            "INSERT INTO chat_history (session_id, role, message, timestamp, model, annotation, stopped, truncated, thinking)
+             SELECT ?1, role, message, timestamp, model, annotation, stopped, truncated, thinking
+             FROM chat_history WHERE id = ?2",
+            params![fork_id, message_id],
+        )?;
+        let copy_id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO message_stats (message_id, prompt_eval_count, eval_count, prompt_eval_duration, eval_duration,
+                 total_duration, seed, temperature, num_ctx)
+             SELECT ?1, prompt_eval_count, eval_count, prompt_eval_duration, eval_duration, total_duration, seed,
+                 temperature, num_ctx
+             FROM message_stats WHERE message_id = ?2",
+            params![copy_id, message_id],
+        )?;
+        tx.execute(
+            "INSERT INTO message_images (message_id, data)
+             SELECT ?1, data FROM message_images WHERE message_id = ?2 ORDER BY id ASC",
+            params![copy_id, message_id],
+        )?;
+        tx.execute(
+            "INSERT INTO attachments (session_id, message_id, file_name, content, byte_count, created_at)
+             SELECT ?1, ?2, file_name, content, byte_count, created_at FROM attachments WHERE message_id = ?3 ORDER BY id ASC",
+            params![fork_id, copy_id, message_id],
+        )?;
+    }
+
+    record_audit(&tx, AUDIT_SESSION_CREATED, Some(fork_id), Some(&title))?;
+    tx.commit()?;
+    Ok(fork_id)
+}
+
+/// Overrides a session's creation and last-activity times, e.g. with those of an imported chat.
+pub fn set_session_times(conn: &Connection, session_id: i64, created_at: Option<&str>, updated_at: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET created_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', ?1), created_at),
+            updated_at = COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', ?2), updated_at)
+         WHERE id = ?3",
+        params![created_at, updated_at, session_id],
+    )?;
+    Ok(())
+}
+
+/// Returns the most recent timestamp each model generated a message at.
+pub fn fetch_model_last_used(conn: &Connection) -> Result<std::collections::HashMap<String, String>> {
+    let mut stmt = conn.prepare(
+        "SELECT model, MAX(timestamp) FROM chat_history WHERE model IS NOT NULL GROUP BY model",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
 }
 
-pub async fn get_or_create_session(conn: &Arc<Mutex<Connection>>, title: &str) -> Result<i64, String> {
-    let conn = conn.lock().await;
+pub async fn get_or_create_session(conn: &DbPool, title: &str) -> Result<i64, String> {
+    let conn = checkout(conn).map_err(|e| e.to_string())?;
 
     // Check if a session with the given title exists
     let mut stmt = conn
@@ -204,35 +1689,335 @@ pub async fn get_or_create_session(conn: &Arc<Mutex<Connection>>, title: &str) -
         Ok(id)
     } else {
         // If not, create a new session
-        conn.execute(
-            "INSERT INTO chat_sessions (title) VALUES (?1)",
-            rusqlite::params![title],
-        )
-        .map_err(|e| e.to_string())?;
+        create_session(&conn, title).map_err(|e| e.to_string())
+    }
+}
 
-        Ok(conn.last_insert_rowid())
+/// Returns the messages of a session as `role: message` lines and the id of the newest one.
+pub fn fetch_session_text(conn: &Connection, session_id: i64) -> Result<(String, Option<i64>)> {
+    let mut stmt = conn.prepare(
+        "SELECT id, role, message FROM chat_history WHERE session_id = ?1 ORDER BY id ASC",
+    )?;
+    let mut text = String::new();
+    let mut last_id = None;
+    let rows = stmt.query_map(params![session_id], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+    for row in rows {
+        let (id, role, message) = row?;
+        if !message.is_empty() {
+            text.push_str(&format!("{}: {}\n\n", role, message));
+        }
+        last_id = Some(id);
     }
+    Ok((text, last_id))
 }
 
-/// Fetches the chat history for a given session.
-pub async fn fetch_chat_history(
+/// Stores a session's embedding, replacing any earlier one.
+pub fn save_session_embedding(
+    conn: &Connection,
     session_id: i64,
-    db: State<'_, Arc<Mutex<Connection>>>,
-) -> Result<Vec<ChatMessage>> {
-    let conn = db.lock().await;
+    model: &str,
+    embedding: &[u8],
+    last_message_id: i64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO session_embeddings (session_id, model, embedding, last_message_id, updated_at)
+         VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+         ON CONFLICT(session_id) DO UPDATE SET
+            model = excluded.model,
+            embedding = excluded.embedding,
+            last_message_id = excluded.last_message_id,
+            updated_at = excluded.updated_at",
+        params![session_id, model, embedding, last_message_id],
+    )?;
+    Ok(())
+}
+
+/// Returns the ids of sessions whose embedding is missing, outdated or from another model.
+pub fn fetch_sessions_needing_embedding(conn: &Connection, model: &str) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT s.id FROM chat_sessions s
+         JOIN (SELECT session_id, MAX(id) AS last_id FROM chat_history GROUP BY session_id) h
+           ON h.session_id = s.id
+         LEFT JOIN session_embeddings e ON e.session_id = s.id
+         WHERE e.session_id IS NULL OR e.model != ?1 OR e.last_message_id < h.last_id",
+    )?;
+    let ids = stmt.query_map(params![model], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Returns the id, title and raw embedding of every existing session embedded with `model`.
+pub fn fetch_session_embeddings(conn: &Connection, model: &str) -> Result<Vec<(i64, String, Vec<u8>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT s.id, s.title, e.embedding FROM session_embeddings e
+         JOIN chat_sessions s ON s.id = e.session_id
+         WHERE e.model = ?1",
+    )?;
+    let rows = stmt.query_map(params![model], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+// Map a row selected as `id, session_id, role, message, timestamp, annotation, stopped, truncated, thinking`
+fn chat_message_from_row(row: &rusqlite::Row) -> Result<ChatMessage> {
+    Ok(ChatMessage {
+        id: row.get(0)?,
+        session_id: row.get(1)?,
+        role: row.get(2)?,
+        message: row.get(3)?,
+        timestamp: row.get(4)?,
+        annotation: row.get(5)?,
+        stopped: row.get(6)?,
+        truncated: row.get(7)?,
+        thinking: row.get(8)?,
+    })
+}
+
+/// Returns a single message by id.
+pub fn get_chat_message(conn: &Connection, message_id: i64) -> Result<Option<ChatMessage>> {
+    conn.query_row(
+        "SELECT id, session_id, role, message, timestamp, annotation, stopped, truncated, thinking FROM chat_history WHERE id = ?1",
+        params![message_id],
+        chat_message_from_row,
+    )
+    .optional()
+}
+
+/// Returns the role and content of the nearest non-system message before `before_id` in a session.
+pub fn fetch_previous_message(conn: &Connection, session_id: i64, before_id: i64) -> Result<Option<(String, String)>> {
+    conn.query_row(
+        "SELECT role, message FROM chat_history
+         WHERE session_id = ?1 AND id < ?2 AND role != 'system'
+         ORDER BY id DESC LIMIT 1",
+        params![session_id, before_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+}
+
+/// Number of messages stored in a session.
+pub fn count_session_messages(conn: &Connection, session_id: i64) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM chat_history WHERE session_id = ?1",
+        params![session_id],
+        |row| row.get(0),
+    )
+}
+
+/// Counts a session's messages, characters and approximate tokens and finds its first and last message times.
+pub fn fetch_session_stats(conn: &Connection, session_id: i64) -> Result<SessionStats> {
+    conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(message)), 0), COALESCE(SUM((LENGTH(message) + 3) / 4), 0),
+                MIN(timestamp), MAX(timestamp)
+         FROM chat_history WHERE session_id = ?1",
+        params![session_id],
+        |row| {
+            Ok(SessionStats {
+                session_id,
+                message_count: row.get(0)?,
+                char_count: row.get(1)?,
+                token_count: row.get(2)?,
+                first_timestamp: row.get(3)?,
+                last_timestamp: row.get(4)?,
+            })
+        },
+    )
+}
+
+/// Returns up to `limit` messages of a session with an id greater than `after_id`, oldest first.
+pub fn fetch_chat_history_batch(conn: &Connection, session_id: i64, after_id: i64, limit: i64) -> Result<Vec<ChatMessage>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, session_id, role, message, timestamp, annotation, stopped, truncated, thinking FROM chat_history
+         WHERE session_id = ?1 AND id > ?2 ORDER BY id ASC LIMIT ?3",
+    )?;
+    let messages = stmt.query_map(params![session_id, after_id, limit], chat_message_from_row)?;
+    messages.collect()
+}
+
+/// Stars or unstars a message; `None` flips the current state. Returns the new state.
+pub fn set_message_favorite(conn: &Connection, message_id: i64, favorite: Option<bool>) -> Result<bool> {
+    let favorite = match favorite {
+        Some(favorite) => favorite,
+        None => !conn.query_row(
+            "SELECT is_favorite FROM chat_history WHERE id = ?1",
+            params![message_id],
+            |row| row.get::<_, bool>(0),
+        )?,
+    };
+    let updated = conn.execute(
+        "UPDATE chat_history SET is_favorite = ?1 WHERE id = ?2",
+        params![favorite, message_id],
+    )?;
+    if updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(favorite)
+}
+
+/// Returns the ids of a session's starred messages.
+pub fn fetch_session_favorites(conn: &Connection, session_id: i64) -> Result<std::collections::HashSet<i64>> {
+    let mut stmt = conn.prepare("SELECT id FROM chat_history WHERE session_id = ?1 AND is_favorite = 1")?;
+    let ids = stmt.query_map(params![session_id], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Lists starred messages across all sessions, archived ones included, newest first.
+pub fn fetch_favorite_messages(conn: &Connection) -> Result<Vec<FavoriteMessage>> {
+    let mut stmt = conn.prepare(
+        "SELECT h.id, h.session_id, h.role, h.message, h.timestamp, h.annotation, h.stopped, h.truncated, h.thinking,
+                s.title, s.archived
+         FROM chat_history h
+         JOIN chat_sessions s ON s.id = h.session_id
+         WHERE h.is_favorite = 1
+         ORDER BY h.id DESC",
+    )?;
+    let favorites = stmt.query_map([], |row| {
+        Ok(FavoriteMessage {
+            message: chat_message_from_row(row)?,
+            session_title: row.get(9)?,
+            session_archived: row.get(10)?,
+        })
+    })?;
+    favorites.collect()
+}
+
+// Characters of context kept on each side of a match in search snippets
+const SNIPPET_CONTEXT_CHARS: usize = 60;
+
+/// Finds messages matching `query` across all sessions, best matches first.
+///
+/// Uses the `chat_history_fts` index when available, matching every word of the query.
+/// Otherwise falls back to a `LIKE` scan for the whole query, newest first.
+pub fn search_messages(conn: &Connection, query: &str, limit: i64) -> Result<Vec<MessageSearchResult>> {
+    if table_exists(conn, "chat_history_fts")? {
+        // Quote each word so FTS5 operators in the query are matched literally
+        let fts_query = query
+            .split_whitespace()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut stmt = conn.prepare(
+            "SELECT h.id, h.session_id, h.role, h.message, h.timestamp, h.annotation, h.stopped, h.truncated, h.thinking,
+                    s.title, snippet(chat_history_fts, 0, '', '', '…', 16)
+             FROM chat_history_fts
+             JOIN chat_history h ON h.id = chat_history_fts.rowid
+             JOIN chat_sessions s ON s.id = h.session_id
+             WHERE chat_history_fts MATCH ?1
+             ORDER BY rank LIMIT ?2",
+        )?;
+        let results = stmt.query_map(params![fts_query, limit], |row| {
+            Ok(MessageSearchResult {
+                message: chat_message_from_row(row)?,
+                session_title: row.get(9)?,
+                snippet: row.get(10)?,
+            })
+        })?;
+        return results.collect();
+    }
+
+    let pattern = format!(
+        "%{}%",
+        query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+    );
     let mut stmt = conn.prepare(
-        "SELECT id, session_id, role, message, timestamp FROM chat_history WHERE session_id = ?1 ORDER BY id ASC",
+        "SELECT h.id, h.session_id, h.role, h.message, h.timestamp, h.annotation, h.stopped, h.truncated, h.thinking, s.title
+         FROM chat_history h
+         JOIN chat_sessions s ON s.id = h.session_id
+         WHERE h.message LIKE ?1 ESCAPE '\\'
+         ORDER BY h.id DESC LIMIT ?2",
+    )?;
+    let results = stmt.query_map(params![pattern, limit], |row| {
+        let message = chat_message_from_row(row)?;
+        let snippet = snippet_around(&message.message, query);
+        Ok(MessageSearchResult {
+            message,
+            session_title: row.get(9)?,
+            snippet,
+        })
+    })?;
+    results.collect()
+}
+
+// Cut `text` down to the first case-insensitive occurrence of `query` and some context around it
+fn snippet_around(text: &str, query: &str) -> String {
+    let lower = text.to_lowercase();
+    let match_start = lower
+        .find(&query.to_lowercase())
+        .and_then(|byte| text.get(..byte))
+        .map(|before| before.chars().count())
+        .unwrap_or(0);
+
+    let from = match_start.saturating_sub(SNIPPET_CONTEXT_CHARS);
+    let len = SNIPPET_CONTEXT_CHARS * 2 + query.chars().count();
+    let mut snippet: String = text.chars().skip(from).take(len).collect();
+    if from > 0 {
+        snippet.insert(0, '…');
+    }
+    if text.chars().count() > from + len {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// Groups a flat, chronological history into user/assistant turns.
+///
+/// Consecutive user messages each start their own turn, and an assistant message that
+/// doesn't follow a user message gets a turn without one. Other roles are skipped.
+pub fn group_turns(messages: Vec<ChatMessage>) -> Vec<ChatTurn> {
+    let mut turns: Vec<ChatTurn> = Vec::new();
+    for message in messages {
+        match message.role.as_str() {
+            "user" => turns.push(ChatTurn { user: Some(message), assistant: None }),
+            "assistant" => match turns.last_mut() {
+                Some(turn) if turn.user.is_some() && turn.assistant.is_none() => turn.assistant = Some(message),
+                _ => turns.push(ChatTurn { user: None, assistant: Some(message) }),
+            },
+            _ => {}
+        }
+    }
+    turns
+}
+
+/// Appends an entry to the audit log.
+pub fn record_audit(conn: &Connection, action: &str, target_id: Option<i64>, detail: Option<&str>) -> Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (action, target_id, detail, timestamp)
+         VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![action, target_id, detail],
     )?;
+    Ok(())
+}
 
-    let messages_iter = stmt.query_map(params![session_id], |row| {
-        Ok(ChatMessage {
+/// Returns audit log entries, newest first, optionally restricted to one action type.
+pub fn fetch_audit_log(conn: &Connection, action: Option<&str>, limit: i64, offset: i64) -> Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, action, target_id, detail, timestamp FROM audit_log
+         WHERE ?1 IS NULL OR action = ?1
+         ORDER BY id DESC LIMIT ?2 OFFSET ?3",
+    )?;
+    let entries = stmt.query_map(params![action, limit, offset], |row| {
+        Ok(AuditEntry {
             id: row.get(0)?,
-            session_id: row.get(1)?,
-            role: row.get(2)?,
-            message: row.get(3)?,
+            action: row.get(1)?,
+            target_id: row.get(2)?,
+            detail: row.get(3)?,
             timestamp: row.get(4)?,
         })
     })?;
+    entries.collect()
+}
+
+/// Fetches the chat history for a given session.
+pub async fn fetch_chat_history(
+    session_id: i64,
+    db: State<'_, DbPool>,
+) -> Result<Vec<ChatMessage>> {
+    let conn = checkout(&db)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, session_id, role, message, timestamp, annotation, stopped, truncated, thinking FROM chat_history WHERE session_id = ?1 ORDER BY id ASC",
+    )?;
+
+    let messages_iter = stmt.query_map(params![session_id], chat_message_from_row)?;
 
     let mut messages = Vec::new();
     for message in messages_iter {
@@ -242,32 +2027,639 @@ pub async fn fetch_chat_history(
     Ok(messages)
 }
 
+/// Returns one page of a session's messages. Pages run oldest first, or newest first with
+/// `newest_first`, in which case `offset` counts back from the newest message.
+/// A `None` limit returns every message after `offset`.
+pub fn fetch_chat_history_page(
+    conn: &Connection,
+    session_id: i64,
+    offset: i64,
+    limit: Option<i64>,
+    newest_first: bool,
+) -> Result<Vec<ChatMessage>> {
+    let sql = format!(
+        "SELECT id, session_id, role, message, timestamp, annotation, stopped, truncated, thinking
+         FROM chat_history WHERE session_id = ?1 ORDER BY id {} LIMIT ?2 OFFSET ?3",
+        if newest_first { "DESC" } else { "ASC" }
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    // SQLite treats a negative LIMIT as no limit
+    let messages = stmt.query_map(params![session_id, limit.unwrap_or(-1), offset], chat_message_from_row)?;
+    messages.collect()
+}
+
+/// Sets the note on a message; `None` or an empty note removes it.
+pub async fn set_message_annotation(
+    message_id: i64,
+    note: Option<String>,
+    db: State<'_, DbPool>,
+) -> Result<()> {
+    let note = note.filter(|n| !n.trim().is_empty());
+    let conn = checkout(&db)?;
+    conn.execute(
+        "UPDATE chat_history SET annotation = ?1 WHERE id = ?2",
+        params![note, message_id],
+    )?;
+    Ok(())
+}
+
 // Save chat history
 pub async fn save_chat_message(
     session_id: i64,
     role: &str,
     message: &str,
-    db: State<'_, Arc<Mutex<Connection>>>,
-) -> Result<()> {
+    model: Option<&str>,
+    db: State<'_, DbPool>,
+) -> Result<i64> {
 
     // Ensure there's an active session
     if session_id <= 0 {
-        return Err(rusqlite::Error::ToSqlConversionFailure(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(rusqlite::Error::ToSqlConversionFailure(Box::new(std::io::Error::other(
             "No active chat session found.",
         ))));
     }
     
-    let conn = db.lock().await;
+    let conn = checkout(&db)?;
 
     conn.execute(
-        "INSERT INTO chat_history (session_id, role, message) VALUES (?1, ?2, ?3)",
-        params![session_id, role, message],
+        "INSERT INTO chat_history (session_id, role, message, model, timestamp)
+         VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![session_id, role, message, model],
     )
-    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(std::io::Error::new(
-        std::io::ErrorKind::Other,
+    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(std::io::Error::other(
         format!("Failed to save chat history: {}", e),
     ))))?;
+    let message_id = conn.last_insert_rowid();
+
+    touch_session(&conn, session_id)?;
+
+    Ok(message_id)
+}
+
+/// Marks a session as updated now.
+pub fn touch_session(conn: &Connection, session_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_sessions SET updated_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now') WHERE id = ?1",
+        params![session_id],
+    )?;
+    Ok(())
+}
+
+/// Attaches base64-encoded images to a message.
+pub fn save_message_images(conn: &Connection, message_id: i64, images: &[String]) -> Result<()> {
+    let mut stmt = conn.prepare("INSERT INTO message_images (message_id, data) VALUES (?1, ?2)")?;
+    for image in images {
+        stmt.execute(params![message_id, image])?;
+    }
+    Ok(())
+}
 
+/// Returns the images of a session's messages, keyed by message id, in attachment order.
+pub fn fetch_session_images(conn: &Connection, session_id: i64) -> Result<std::collections::HashMap<i64, Vec<String>>> {
+    let mut stmt = conn.prepare(
+        "SELECT i.message_id, i.data FROM message_images i
+         JOIN chat_history h ON h.id = i.message_id
+         WHERE h.session_id = ?1 ORDER BY i.id ASC",
+    )?;
+    let mut images: std::collections::HashMap<i64, Vec<String>> = std::collections::HashMap::new();
+    let rows = stmt.query_map(params![session_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    for row in rows {
+        let (message_id, data) = row?;
+        images.entry(message_id).or_default().push(data);
+    }
+    Ok(images)
+}
+
+/// Returns the model and sampling settings behind each of a session's replies, keyed by message id.
+pub fn fetch_session_generation_params(conn: &Connection, session_id: i64) -> Result<std::collections::HashMap<i64, GenerationParams>> {
+    let mut stmt = conn.prepare(
+        "SELECT h.id, h.model, s.temperature, s.seed, s.num_ctx FROM chat_history h
+         LEFT JOIN message_stats s ON s.message_id = h.id
+         WHERE h.session_id = ?1 AND h.role = 'assistant'",
+    )?;
+    let rows = stmt.query_map(params![session_id], |row| {
+        Ok((
+            row.get(0)?,
+            GenerationParams {
+                model: row.get(1)?,
+                temperature: row.get(2)?,
+                seed: row.get(3)?,
+                num_ctx: row.get(4)?,
+            },
+        ))
+    })?;
+    rows.collect()
+}
+
+/// Stores a file to be sent with the session's next prompt.
+pub fn add_attachment(
+    conn: &Connection,
+    session_id: i64,
+    file_name: &str,
+    content: &str,
+    byte_count: i64,
+) -> Result<Attachment> {
+    // Fail with QueryReturnedNoRows for an unknown session
+    conn.query_row("SELECT id FROM chat_sessions WHERE id = ?1", params![session_id], |row| row.get::<_, i64>(0))?;
+    conn.execute(
+        "INSERT INTO attachments (session_id, file_name, content, byte_count, created_at)
+         VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![session_id, file_name, content, byte_count],
+    )?;
+    conn.query_row(
+        "SELECT id, session_id, message_id, file_name, content, byte_count, created_at FROM attachments WHERE id = ?1",
+        params![conn.last_insert_rowid()],
+        attachment_from_row,
+    )
+}
+
+/// Links the session's pending attachments to `message_id` and returns them, oldest first.
+pub fn claim_pending_attachments(conn: &Connection, session_id: i64, message_id: i64) -> Result<Vec<Attachment>> {
+    conn.execute(
+        "UPDATE attachments SET message_id = ?1 WHERE session_id = ?2 AND message_id IS NULL",
+        params![message_id, session_id],
+    )?;
+    let mut stmt = conn.prepare(
+        "SELECT id, session_id, message_id, file_name, content, byte_count, created_at
+         FROM attachments WHERE message_id = ?1 ORDER BY id ASC",
+    )?;
+    let attachments = stmt.query_map(params![message_id], attachment_from_row)?;
+    attachments.collect()
+}
+
+/// Returns the attachments sent with a session's messages, keyed by message id.
+pub fn fetch_session_attachments(conn: &Connection, session_id: i64) -> Result<std::collections::HashMap<i64, Vec<Attachment>>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, session_id, message_id, file_name, content, byte_count, created_at
+         FROM attachments WHERE session_id = ?1 AND message_id IS NOT NULL ORDER BY id ASC",
+    )?;
+    let mut attachments: std::collections::HashMap<i64, Vec<Attachment>> = std::collections::HashMap::new();
+    for attachment in stmt.query_map(params![session_id], attachment_from_row)? {
+        let attachment = attachment?;
+        if let Some(message_id) = attachment.message_id {
+            attachments.entry(message_id).or_default().push(attachment);
+        }
+    }
+    Ok(attachments)
+}
+
+/// Deletes a message together with its generation stats, images and attachments.
+pub fn delete_chat_message(conn: &Connection, message_id: i64) -> Result<()> {
+    conn.execute("DELETE FROM message_stats WHERE message_id = ?1", params![message_id])?;
+    conn.execute("DELETE FROM message_images WHERE message_id = ?1", params![message_id])?;
+    conn.execute("DELETE FROM attachments WHERE message_id = ?1", params![message_id])?;
+    conn.execute("DELETE FROM chat_history WHERE id = ?1", params![message_id])?;
+    Ok(())
+}
+
+/// Deletes a single message and its stats, reporting whether later messages follow it.
+pub fn delete_message(conn: &mut Connection, message_id: i64) -> Result<DeletedMessage> {
+    let tx = conn.transaction()?;
+    let session_id: i64 = tx.query_row(
+        "SELECT session_id FROM chat_history WHERE id = ?1",
+        params![message_id],
+        |row| row.get(0),
+    )?;
+    let mid_conversation: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM chat_history WHERE session_id = ?1 AND id > ?2)",
+        params![session_id, message_id],
+        |row| row.get(0),
+    )?;
+
+    delete_chat_message(&tx, message_id)?;
+    // The stored embedding covers the removed message, so rebuild it
+    tx.execute("DELETE FROM session_embeddings WHERE session_id = ?1", params![session_id])?;
+    tx.commit()?;
+
+    Ok(DeletedMessage { session_id, mid_conversation })
+}
+
+/// Writes the final content and reasoning of a streamed assistant reply and whether it was
+/// stopped early by the user or truncated by the length cap.
+pub fn finalize_message(
+    conn: &Connection,
+    message_id: i64,
+    content: &str,
+    thinking: Option<&str>,
+    stopped: bool,
+    truncated: bool,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE chat_history SET message = ?1, thinking = ?2, stopped = ?3, truncated = ?4 WHERE id = ?5",
+        params![content, thinking, stopped, truncated, message_id],
+    )?;
+    Ok(())
+}
+
+/// Replaces a message's content and deletes every later message of its session, in one
+/// transaction. Returns the id of the message's session.
+pub fn edit_message(conn: &mut Connection, message_id: i64, new_content: &str) -> Result<i64> {
+    let tx = conn.transaction()?;
+    let session_id: i64 = tx.query_row(
+        "SELECT session_id FROM chat_history WHERE id = ?1",
+        params![message_id],
+        |row| row.get(0),
+    )?;
+
+    tx.execute(
+        "UPDATE chat_history SET message = ?1, thinking = NULL, stopped = 0, truncated = 0 WHERE id = ?2",
+        params![new_content, message_id],
+    )?;
+    tx.execute(
+        "DELETE FROM message_stats WHERE message_id IN
+            (SELECT id FROM chat_history WHERE session_id = ?1 AND id > ?2)",
+        params![session_id, message_id],
+    )?;
+    tx.execute(
+        "DELETE FROM message_images WHERE message_id IN
+            (SELECT id FROM chat_history WHERE session_id = ?1 AND id > ?2)",
+        params![session_id, message_id],
+    )?;
+    tx.execute(
+        "DELETE FROM attachments WHERE message_id IN
+            (SELECT id FROM chat_history WHERE session_id = ?1 AND id > ?2)",
+        params![session_id, message_id],
+    )?;
+    tx.execute(
+        "DELETE FROM chat_history WHERE session_id = ?1 AND id > ?2",
+        params![session_id, message_id],
+    )?;
+    // The stored embedding covers the removed messages, so rebuild it
+    tx.execute("DELETE FROM session_embeddings WHERE session_id = ?1", params![session_id])?;
+    touch_session(&tx, session_id)?;
+    tx.commit()?;
+
+    Ok(session_id)
+}
+
+/// Inserts all messages in a single transaction using one prepared statement.
+pub fn insert_messages(
+    conn: &mut Connection,
+    session_id: i64,
+    messages: &[NewChatMessage],
+) -> Result<usize> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO chat_history (session_id, role, message, timestamp)
+             VALUES (?1, ?2, ?3, COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', ?4), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')))",
+        )?;
+        for message in messages {
+            stmt.execute(params![session_id, message.role, message.content, message.timestamp])?;
+        }
+    }
+    touch_session(&tx, session_id)?;
+    tx.commit()?;
+
+    Ok(messages.len())
+}
+
+/// Returns the id and backup settings of every session, oldest first.
+pub fn fetch_backup_sessions(conn: &Connection) -> Result<Vec<(i64, BackupSession)>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, title, created_at, updated_at, model, system_prompt, options, show_thinking, pinned, archived
+         FROM chat_sessions ORDER BY id ASC",
+    )?;
+    let sessions = stmt.query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            BackupSession {
+                title: row.get(1)?,
+                created_at: row.get(2)?,
+                updated_at: row.get(3)?,
+                model: row.get(4)?,
+                system_prompt: row.get(5)?,
+                options: options_from_json(row.get(6)?),
+                show_thinking: row.get(7)?,
+                pinned: row.get(8)?,
+                archived: row.get(9)?,
+            },
+        ))
+    })?;
+    sessions.collect()
+}
+
+/// Returns up to `limit` messages of a session after `after_id` with their stats, oldest first,
+/// each paired with its id.
+pub fn fetch_backup_messages_batch(
+    conn: &Connection,
+    session_id: i64,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<(i64, BackupMessage)>> {
+    let mut stmt = conn.prepare(
+        "SELECT h.id, h.role, h.message, h.timestamp, h.model, h.annotation, h.stopped, h.truncated, h.thinking,
+                s.message_id, s.prompt_eval_count, s.eval_count, s.prompt_eval_duration, s.eval_duration, s.total_duration,
+                s.seed, s.temperature, s.num_ctx
+         FROM chat_history h
+         LEFT JOIN message_stats s ON s.message_id = h.id
+         WHERE h.session_id = ?1 AND h.id > ?2 ORDER BY h.id ASC LIMIT ?3",
+    )?;
+    let messages = stmt.query_map(params![session_id, after_id, limit], |row| {
+        let stats = match row.get::<_, Option<i64>>(9)? {
+            Some(_) => Some(GenerationStats {
+                prompt_eval_count: row.get(10)?,
+                eval_count: row.get(11)?,
+                prompt_eval_duration: row.get(12)?,
+                eval_duration: row.get(13)?,
+                total_duration: row.get(14)?,
+                seed: row.get(15)?,
+                temperature: row.get(16)?,
+                num_ctx: row.get(17)?,
+                tokens_per_second: None,
+            }),
+            None => None,
+        };
+        Ok((
+            row.get(0)?,
+            BackupMessage {
+                role: row.get(1)?,
+                message: row.get(2)?,
+                timestamp: row.get(3)?,
+                model: row.get(4)?,
+                annotation: row.get(5)?,
+                stopped: row.get(6)?,
+                truncated: row.get(7)?,
+                thinking: row.get(8)?,
+                stats: stats.map(GenerationStats::with_rate),
+            },
+        ))
+    })?;
+    messages.collect()
+}
+
+fn insert_backup_message(conn: &Connection, session_id: i64, message: &BackupMessage) -> Result<()> {
+    conn.execute(
+        "INSERT INTO chat_history (session_id, role, message, timestamp, model, annotation, stopped, truncated, thinking)
+         VALUES (?1, ?2, ?3, COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', ?4), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')), ?5, ?6, ?7, ?8, ?9)",
+        params![
+            session_id,
+            message.role,
+            message.message,
+            message.timestamp,
+            message.model,
+            message.annotation,
+            message.stopped,
+            message.truncated,
+            message.thinking
+        ],
+    )?;
+    if let Some(stats) = &message.stats {
+        save_message_stats(conn, conn.last_insert_rowid(), stats)?;
+    }
+    Ok(())
+}
+
+/// Restores one session of a backup in a single transaction.
+///
+/// A session whose title already exists is skipped, or with `merge` gets the backup's
+/// messages that it doesn't already have (same role, content and timestamp). Timestamps are
+/// compared as stored; a message whose timestamp is missing or unreadable matches on role and content.
+pub fn import_backup_session(
+    conn: &mut Connection,
+    session: &BackupSession,
+    messages: &[BackupMessage],
+    merge: bool,
+) -> Result<BackupImport> {
+    let tx = conn.transaction()?;
+    let existing: Option<i64> = tx
+        .query_row(
+            "SELECT id FROM chat_sessions WHERE title = ?1 ORDER BY id LIMIT 1",
+            params![session.title],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    let outcome = match existing {
+        Some(_) if !merge => BackupImport::Skipped,
+        Some(session_id) => {
+            let mut added = 0;
+            for message in messages {
+                let present: bool = tx.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM chat_history
+                     WHERE session_id = ?1 AND role = ?2 AND message = ?3
+                       AND (strftime('%Y-%m-%dT%H:%M:%SZ', ?4) IS NULL OR timestamp IS strftime('%Y-%m-%dT%H:%M:%SZ', ?4)))",
+                    params![session_id, message.role, message.message, message.timestamp],
+                    |row| row.get(0),
+                )?;
+                if !present {
+                    insert_backup_message(&tx, session_id, message)?;
+                    added += 1;
+                }
+            }
+            BackupImport::Merged { added }
+        }
+        None => {
+            let options = session.options.as_ref().and_then(|o| serde_json::to_string(o).ok());
+            tx.execute(
+                "INSERT INTO chat_sessions
+                    (title, created_at, updated_at, model, system_prompt, options, show_thinking, pinned, archived)
+                 VALUES (?1, COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', ?2), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')), COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', ?3), strftime('%Y-%m-%dT%H:%M:%SZ', 'now')), ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    session.title,
+                    session.created_at,
+                    session.updated_at,
+                    session.model,
+                    session.system_prompt,
+                    options,
+                    session.show_thinking,
+                    session.pinned,
+                    session.archived
+                ],
+            )?;
+            let session_id = tx.last_insert_rowid();
+            record_audit(&tx, AUDIT_SESSION_CREATED, Some(session_id), Some(&session.title))?;
+            for message in messages {
+                insert_backup_message(&tx, session_id, message)?;
+            }
+            BackupImport::Created
+        }
+    };
+
+    tx.commit()?;
+    Ok(outcome)
+}
+
+// Save many chat messages at once
+pub async fn save_messages_bulk(
+    session_id: i64,
+    messages: &[NewChatMessage],
+    db: State<'_, DbPool>,
+) -> Result<usize> {
+    if session_id <= 0 {
+        return Err(rusqlite::Error::ToSqlConversionFailure(Box::new(std::io::Error::other(
+            "No active chat session found.",
+        ))));
+    }
+
+    let mut conn = checkout(&db)?;
+    insert_messages(&mut conn, session_id, messages)
+}
+
+/// Stores the statistics Ollama reported for an assistant message.
+pub fn save_message_stats(
+    conn: &Connection,
+    message_id: i64,
+    stats: &GenerationStats,
+) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO message_stats
+            (message_id, prompt_eval_count, eval_count, prompt_eval_duration, eval_duration, total_duration, seed,
+             temperature, num_ctx)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            message_id,
+            stats.prompt_eval_count,
+            stats.eval_count,
+            stats.prompt_eval_duration,
+            stats.eval_duration,
+            stats.total_duration,
+            stats.seed,
+            stats.temperature,
+            stats.num_ctx
+        ],
+    )?;
     Ok(())
 }
+
+/// Returns the generation statistics stored for an assistant message.
+pub fn get_message_stats(conn: &Connection, message_id: i64) -> Result<Option<GenerationStats>> {
+    let stats = conn
+        .query_row(
+            "SELECT prompt_eval_count, eval_count, prompt_eval_duration, eval_duration, total_duration, seed,
+                    temperature, num_ctx
+             FROM message_stats WHERE message_id = ?1",
+            params![message_id],
+            |row| {
+                Ok(GenerationStats {
+                    prompt_eval_count: row.get(0)?,
+                    eval_count: row.get(1)?,
+                    prompt_eval_duration: row.get(2)?,
+                    eval_duration: row.get(3)?,
+                    total_duration: row.get(4)?,
+                    seed: row.get(5)?,
+                    temperature: row.get(6)?,
+                    num_ctx: row.get(7)?,
+                    tokens_per_second: None,
+                })
+            },
+        )
+        .optional()?;
+    Ok(stats.map(GenerationStats::with_rate))
+}
+
+/// Fetches the generation metrics of every assistant message, oldest first.
+pub async fn fetch_message_metrics(
+    db: State<'_, DbPool>,
+) -> Result<Vec<MessageMetrics>> {
+    let conn = checkout(&db)?;
+    let mut stmt = conn.prepare(
+        "SELECT h.session_id, h.id, h.timestamp, h.model, s.prompt_eval_count, s.eval_count,
+                CASE WHEN s.eval_duration > 0 THEN s.eval_count / (s.eval_duration / 1e9) END
+         FROM chat_history h
+         LEFT JOIN message_stats s ON s.message_id = h.id
+         WHERE h.role = 'assistant'
+         ORDER BY h.id ASC",
+    )?;
+
+    let metrics_iter = stmt.query_map([], |row| {
+        Ok(MessageMetrics {
+            session_id: row.get(0)?,
+            message_id: row.get(1)?,
+            timestamp: row.get(2)?,
+            model: row.get(3)?,
+            prompt_eval_count: row.get(4)?,
+            eval_count: row.get(5)?,
+            tokens_per_second: row.get(6)?,
+        })
+    })?;
+
+    let mut metrics = Vec::new();
+    for entry in metrics_iter {
+        metrics.push(entry?);
+    }
+
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn stored_timestamps_parse_as_rfc3339() {
+        let conn = open_test_db();
+        let session_id = create_session(&conn, "Test").unwrap();
+        // Written the way rows were before the conversion, by CURRENT_TIMESTAMP
+        conn.execute(
+            "INSERT INTO chat_history (session_id, role, message, timestamp) VALUES (?1, 'user', 'Hi', '2024-05-01 12:30:00')",
+            [session_id],
+        )
+        .unwrap();
+        convert_timestamps_to_rfc3339(&conn).unwrap();
+        // A second run must leave converted values alone
+        convert_timestamps_to_rfc3339(&conn).unwrap();
+
+        let message = fetch_chat_history_batch(&conn, session_id, 0, 1).unwrap().remove(0);
+        let parsed = DateTime::parse_from_rfc3339(&message.timestamp).unwrap();
+        assert_eq!(parsed.to_rfc3339_opts(chrono::SecondsFormat::Secs, true), "2024-05-01T12:30:00Z");
+
+        let (created_at, updated_at): (String, String) = conn
+            .query_row("SELECT created_at, updated_at FROM chat_sessions WHERE id = ?1", [session_id], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        for timestamp in [created_at, updated_at] {
+            assert!(DateTime::parse_from_rfc3339(&timestamp).is_ok(), "{}", timestamp);
+        }
+    }
+
+    #[test]
+    fn audit_and_folder_timestamps_parse_as_rfc3339() {
+        let conn = open_test_db();
+        // Recorded by the insert itself
+        create_session(&conn, "Test").unwrap();
+        // Left over from before the conversion
+        conn.execute("INSERT INTO folders (name, created_at) VALUES ('Old', '2024-05-01 12:30:00')", []).unwrap();
+        convert_remaining_timestamps_to_rfc3339(&conn).unwrap();
+        create_folder(&conn, "New").unwrap();
+
+        let audit: String = conn.query_row("SELECT timestamp FROM audit_log", [], |row| row.get(0)).unwrap();
+        let mut stmt = conn.prepare("SELECT created_at FROM folders ORDER BY id").unwrap();
+        let folders: Vec<String> = stmt.query_map([], |row| row.get(0)).unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(folders[0], "2024-05-01T12:30:00Z");
+        for timestamp in folders.iter().chain([&audit]) {
+            assert!(DateTime::parse_from_rfc3339(timestamp).is_ok(), "{}", timestamp);
+        }
+    }
+
+    #[test]
+    fn merging_a_backup_twice_adds_nothing_the_second_time() {
+        let mut conn = open_test_db();
+        let session = BackupSession { title: "Backup".to_string(), ..Default::default() };
+        let message = |role: &str, text: &str, timestamp: Option<&str>| BackupMessage {
+            role: role.to_string(),
+            message: text.to_string(),
+            timestamp: timestamp.map(str::to_string),
+            model: None,
+            annotation: None,
+            stopped: false,
+            truncated: false,
+            thinking: None,
+            stats: None,
+        };
+        // Exports from before timestamps were stored as RFC 3339
+        let messages = [
+            message("user", "Hi", Some("2024-05-01 12:30:00")),
+            message("assistant", "Hello", Some("2024-05-01 12:30:05")),
+            message("user", "Undated", None),
+        ];
+
+        assert_eq!(import_backup_session(&mut conn, &session, &messages, true).unwrap(), BackupImport::Created);
+        assert_eq!(
+            import_backup_session(&mut conn, &session, &messages, true).unwrap(),
+            BackupImport::Merged { added: 0 }
+        );
+    }
+}
@@ -0,0 +1,31 @@
+// Compares texts word by word
+
+/// Number of words in the longest common subsequence of `a` and `b`.
+fn lcs_len(a: &[&str], b: &[&str]) -> usize {
+    // Single-row dynamic programming table
+    let mut row = vec![0usize; b.len() + 1];
+    for word_a in a {
+        let mut diagonal = 0;
+        for (j, word_b) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if word_a == word_b {
+                diagonal + 1
+            } else {
+                row[j + 1].max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// How different two texts are, from 0.0 (same words in the same order) to 1.0 (nothing in common).
+pub fn word_divergence(a: &str, b: &str) -> f64 {
+    let a: Vec<&str> = a.split_whitespace().collect();
+    let b: Vec<&str> = b.split_whitespace().collect();
+    if a.is_empty() && b.is_empty() {
+        return 0.0;
+    }
+    let common = lcs_len(&a, &b) as f64;
+    1.0 - (2.0 * common) / (a.len() + b.len()) as f64
+}
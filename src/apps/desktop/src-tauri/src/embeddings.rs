@@ -0,0 +1,166 @@
+// Embeds session text and ranks sessions by semantic similarity
+
+use crate::db;
+use crate::ollama_api;
+
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Config key naming the model used for session embeddings.
+pub const EMBEDDING_MODEL_KEY: &str = "embedding_model";
+pub const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+
+/// Quiet period after the last message before a session is re-embedded.
+const EMBEDDING_DEBOUNCE: Duration = Duration::from_secs(10);
+
+// Embedding models have short context windows, so only the most recent text is embedded
+const MAX_EMBEDDING_CHARS: usize = 8_000;
+
+/// Tracks pending debounced re-embeds, keyed by session id.
+#[derive(Default)]
+pub struct EmbeddingScheduler {
+    pending: HashMap<i64, u64>,
+}
+
+pub fn embedding_model(conn: &Connection) -> String {
+    db::get_config_value(conn, EMBEDDING_MODEL_KEY)
+        .ok()
+        .flatten()
+        .filter(|model| !model.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string())
+}
+
+pub fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Cosine similarity of two vectors; 0 when the lengths differ or either is zero.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let mut dot = 0.0;
+    let mut norm_a = 0.0;
+    let mut norm_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+fn tail_chars(text: &str, max_chars: usize) -> &str {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text;
+    }
+    let start = text.char_indices().nth(count - max_chars).map(|(i, _)| i).unwrap_or(0);
+    &text[start..]
+}
+
+// Compute and store the embedding of one session's combined text
+pub async fn update_session_embedding(db_conn: &db::DbPool, session_id: i64) -> Result<(), String> {
+    let (model, text, last_message_id) = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        let (text, last_message_id) = db::fetch_session_text(&conn, session_id).map_err(|e| e.to_string())?;
+        (embedding_model(&conn), text, last_message_id)
+    };
+    let Some(last_message_id) = last_message_id else {
+        return Ok(());
+    };
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+
+    // The database lock is not held while the model runs
+    let embedding = ollama_api::fetch_embedding(&model, tail_chars(&text, MAX_EMBEDDING_CHARS)).await?;
+
+    let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+    db::save_session_embedding(&conn, session_id, &model, &encode_embedding(&embedding), last_message_id)
+        .map_err(|e| e.to_string())
+}
+
+// Embed every session whose embedding is missing or out of date, returning how many were updated
+pub async fn index_sessions(db_conn: &db::DbPool) -> Result<usize, String> {
+    let session_ids = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        db::fetch_sessions_needing_embedding(&conn, &embedding_model(&conn)).map_err(|e| e.to_string())?
+    };
+    for session_id in &session_ids {
+        update_session_embedding(db_conn, *session_id).await?;
+    }
+    Ok(session_ids.len())
+}
+
+// Re-embed a session once no new message has arrived for `EMBEDDING_DEBOUNCE`
+pub fn schedule_session_embedding(
+    scheduler: Arc<Mutex<EmbeddingScheduler>>,
+    db_conn: db::DbPool,
+    session_id: i64,
+) {
+    tauri::async_runtime::spawn(async move {
+        let ticket = {
+            let mut scheduler = scheduler.lock().await;
+            let ticket = scheduler.pending.entry(session_id).or_insert(0);
+            *ticket += 1;
+            *ticket
+        };
+
+        tokio::time::sleep(EMBEDDING_DEBOUNCE).await;
+
+        {
+            let mut scheduler = scheduler.lock().await;
+            // A newer message rescheduled this session
+            if scheduler.pending.get(&session_id) != Some(&ticket) {
+                return;
+            }
+            scheduler.pending.remove(&session_id);
+        }
+
+        if let Err(e) = update_session_embedding(&db_conn, session_id).await {
+            eprintln!("Failed to embed session {}: {}", session_id, e);
+        }
+    });
+}
+
+// Embed the query and return sessions ranked by cosine similarity
+pub async fn semantic_search_sessions(
+    db_conn: &db::DbPool,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<db::SessionMatch>, String> {
+    let model = embedding_model(&db::checkout(db_conn).map_err(|e| e.to_string())?);
+    let query_embedding = ollama_api::fetch_embedding(&model, query).await?;
+
+    let stored = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        db::fetch_session_embeddings(&conn, &model).map_err(|e| e.to_string())?
+    };
+
+    let mut matches: Vec<db::SessionMatch> = stored
+        .into_iter()
+        .map(|(id, title, bytes)| db::SessionMatch {
+            id,
+            title,
+            score: cosine_similarity(&query_embedding, &decode_embedding(&bytes)),
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+
+    Ok(matches)
+}
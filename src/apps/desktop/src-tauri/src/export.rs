@@ -0,0 +1,287 @@
+// Renders stored chat data into export formats
+
+use crate::db::{BackupMessage, BackupSession, ChatMessage, MessageMetrics};
+use std::io::{self, Write};
+
+// Quote a CSV field when it contains a separator, quote or newline
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') || value.contains('\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Renders generation metrics as CSV with a header row.
+pub fn metrics_csv(metrics: &[MessageMetrics]) -> String {
+    let mut csv = String::from(
+        "session_id,message_id,timestamp,model,prompt_eval_count,eval_count,tokens_per_second\n",
+    );
+
+    for entry in metrics {
+        let row = [
+            entry.session_id.to_string(),
+            entry.message_id.to_string(),
+            csv_field(&entry.timestamp),
+            csv_field(entry.model.as_deref().unwrap_or_default()),
+            optional(&entry.prompt_eval_count),
+            optional(&entry.eval_count),
+            entry.tokens_per_second.map(|tps| format!("{:.2}", tps)).unwrap_or_default(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Text formats a session can be rendered to.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Markdown,
+    PlainText,
+    Json,
+}
+
+fn role_label(role: &str) -> &str {
+    match role {
+        "user" => "User",
+        "assistant" => "Assistant",
+        "system" => "System",
+        other => other,
+    }
+}
+
+fn markdown_message(message: &ChatMessage) -> String {
+    format!(
+        "## {}\n\n_{}_\n\n{}\n\n",
+        role_label(&message.role),
+        message.timestamp,
+        message.message.trim_end()
+    )
+}
+
+fn plain_text_message(message: &ChatMessage) -> String {
+    format!(
+        "{} ({}):\n{}\n\n",
+        role_label(&message.role),
+        message.timestamp,
+        message.message.trim_end()
+    )
+}
+
+// `model` is the session's model, shown under the title when set
+fn markdown_header(title: &str, model: Option<&str>) -> String {
+    match model.filter(|m| !m.trim().is_empty()) {
+        Some(model) => format!("# {}\n\n_Model: {}_\n\n", title, model),
+        None => format!("# {}\n\n", title),
+    }
+}
+
+/// Renders a session as a Markdown document; message content is already Markdown and is kept as-is.
+pub fn session_markdown(title: &str, model: Option<&str>, messages: &[ChatMessage]) -> String {
+    let mut markdown = markdown_header(title, model);
+    for message in messages {
+        markdown.push_str(&markdown_message(message));
+    }
+    markdown
+}
+
+/// Renders a session as plain text.
+pub fn session_plain_text(title: &str, messages: &[ChatMessage]) -> String {
+    let mut text = format!("{}\n\n", title);
+    for message in messages {
+        text.push_str(&plain_text_message(message));
+    }
+    text
+}
+
+/// Renders a session as a JSON object with its title and messages.
+pub fn session_json(session_id: i64, title: &str, messages: &[ChatMessage]) -> String {
+    serde_json::json!({
+        "session_id": session_id,
+        "title": title,
+        "messages": messages,
+    })
+    .to_string()
+}
+
+pub fn render_session(
+    format: ExportFormat,
+    session_id: i64,
+    title: &str,
+    model: Option<&str>,
+    messages: &[ChatMessage],
+) -> String {
+    match format {
+        ExportFormat::Markdown => session_markdown(title, model, messages),
+        ExportFormat::PlainText => session_plain_text(title, messages),
+        ExportFormat::Json => session_json(session_id, title, messages),
+    }
+}
+
+/// Payload of the `export-progress` event.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ExportProgress {
+    pub session_id: i64,
+    pub written: usize,
+    pub total: usize,
+}
+
+/// Writes a session export piece by piece, so large sessions never have to be held in memory.
+pub struct SessionWriter<W: Write> {
+    writer: W,
+    format: ExportFormat,
+    written: usize,
+}
+
+impl<W: Write> SessionWriter<W> {
+    /// Writes the document header; `model` is only shown in Markdown.
+    pub fn begin(
+        mut writer: W,
+        format: ExportFormat,
+        session_id: i64,
+        title: &str,
+        model: Option<&str>,
+    ) -> io::Result<Self> {
+        match format {
+            ExportFormat::Markdown => writer.write_all(markdown_header(title, model).as_bytes())?,
+            ExportFormat::PlainText => write!(writer, "{}\n\n", title)?,
+            ExportFormat::Json => write!(
+                writer,
+                "{{\"session_id\":{},\"title\":{},\"messages\":[",
+                session_id,
+                serde_json::Value::from(title)
+            )?,
+        }
+        Ok(Self { writer, format, written: 0 })
+    }
+
+    pub fn write_message(&mut self, message: &ChatMessage) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Markdown => self.writer.write_all(markdown_message(message).as_bytes())?,
+            ExportFormat::PlainText => self.writer.write_all(plain_text_message(message).as_bytes())?,
+            ExportFormat::Json => {
+                if self.written > 0 {
+                    self.writer.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut self.writer, message)?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Closes the document and flushes the writer.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let ExportFormat::Json = self.format {
+            self.writer.write_all(b"]}")?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Format version written to full backups; bump it when the layout changes incompatibly.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// A full backup as read back by `import_all_json`.
+#[derive(Debug, serde::Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub sessions: Vec<BackupEntry>,
+}
+
+/// One session of a full backup together with its messages.
+#[derive(Debug, serde::Deserialize)]
+pub struct BackupEntry {
+    pub session: BackupSession,
+    #[serde(default)]
+    pub messages: Vec<BackupMessage>,
+}
+
+/// Result of `import_all_json`.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct BackupImportSummary {
+    pub created: usize,
+    pub merged: usize,
+    pub skipped: usize,
+    /// Messages inserted into created and merged sessions.
+    pub messages: usize,
+}
+
+/// Writes a full backup session by session, so the database never has to be held in memory.
+///
+/// The document is `{"version", "exported_at", "sessions": [{"session", "messages"}]}`.
+pub struct BackupWriter<W: Write> {
+    writer: W,
+    sessions: usize,
+    messages: usize,
+}
+
+impl<W: Write> BackupWriter<W> {
+    pub fn begin(mut writer: W) -> io::Result<Self> {
+        write!(
+            writer,
+            "{{\"version\":{},\"exported_at\":{},\"sessions\":[",
+            BACKUP_VERSION,
+            serde_json::Value::from(chrono::Local::now().to_rfc3339())
+        )?;
+        Ok(Self { writer, sessions: 0, messages: 0 })
+    }
+
+    /// Opens the entry of a session; its messages follow with `write_message`.
+    pub fn begin_session(&mut self, session: &BackupSession) -> io::Result<()> {
+        if self.sessions > 0 {
+            self.writer.write_all(b",")?;
+        }
+        self.writer.write_all(b"{\"session\":")?;
+        serde_json::to_writer(&mut self.writer, session)?;
+        self.writer.write_all(b",\"messages\":[")?;
+        self.sessions += 1;
+        self.messages = 0;
+        Ok(())
+    }
+
+    pub fn write_message(&mut self, message: &BackupMessage) -> io::Result<()> {
+        if self.messages > 0 {
+            self.writer.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.writer, message)?;
+        self.messages += 1;
+        Ok(())
+    }
+
+    pub fn end_session(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"]}")
+    }
+
+    pub fn sessions(&self) -> usize {
+        self.sessions
+    }
+
+    /// Closes the document and flushes the writer.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(b"]}")?;
+        self.writer.flush()
+    }
+}
+
+/// Renders one exchange as a fine-tuning JSONL line in the `{"messages": [...]}` format.
+pub fn pair_jsonl(system: Option<&str>, user: &str, assistant: &str) -> String {
+    let mut messages = Vec::new();
+    if let Some(system) = system {
+        messages.push(serde_json::json!({ "role": "system", "content": system }));
+    }
+    messages.push(serde_json::json!({ "role": "user", "content": user }));
+    messages.push(serde_json::json!({ "role": "assistant", "content": assistant }));
+    serde_json::json!({ "messages": messages }).to_string()
+}
@@ -0,0 +1,104 @@
+// Parses chat exports from other apps into messages ready to insert
+
+use crate::db::NewChatMessage;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// One conversation of a ChatGPT `conversations.json` export.
+#[derive(Debug, Deserialize)]
+pub struct ChatGptConversation {
+    #[serde(default)]
+    pub title: Option<String>,
+    pub create_time: Option<f64>,
+    pub update_time: Option<f64>,
+    #[serde(default)]
+    pub mapping: HashMap<String, ChatGptNode>,
+    /// Leaf of the branch that was last shown; edits and regenerations create sibling branches.
+    pub current_node: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatGptNode {
+    pub message: Option<ChatGptMessage>,
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatGptMessage {
+    pub author: ChatGptAuthor,
+    pub content: Option<ChatGptContent>,
+    pub create_time: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatGptAuthor {
+    pub role: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatGptContent {
+    /// Text parts are strings; attachments such as images are objects and are dropped.
+    #[serde(default)]
+    pub parts: Vec<serde_json::Value>,
+}
+
+/// Formats a Unix timestamp the way chat timestamps are stored: RFC 3339 in UTC.
+pub fn sqlite_timestamp(seconds: f64) -> Option<String> {
+    chrono::DateTime::from_timestamp(seconds.trunc() as i64, 0)
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+impl ChatGptConversation {
+    pub fn title(&self) -> String {
+        self.title
+            .as_deref()
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .unwrap_or("Imported chat")
+            .to_string()
+    }
+
+    /// Flattens the branch ending at `current_node` into user and assistant messages, oldest first.
+    /// System and tool messages and messages without text are skipped.
+    pub fn messages(&self) -> Vec<NewChatMessage> {
+        let mut messages = Vec::new();
+        let mut next = self.current_node.as_deref();
+        // Walk from the leaf to the root; the step bound guards against a malformed cycle
+        for _ in 0..=self.mapping.len() {
+            let Some(node) = next.and_then(|id| self.mapping.get(id)) else {
+                break;
+            };
+            if let Some(message) = &node.message {
+                let text = message
+                    .content
+                    .as_ref()
+                    .map(|c| c.parts.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>().join("\n"))
+                    .unwrap_or_default();
+                if matches!(message.author.role.as_str(), "user" | "assistant") && !text.trim().is_empty() {
+                    messages.push(NewChatMessage {
+                        role: message.author.role.clone(),
+                        content: text,
+                        timestamp: message.create_time.and_then(sqlite_timestamp),
+                    });
+                }
+            }
+            next = node.parent.as_deref();
+        }
+        messages.reverse();
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imported_timestamps_round_trip() {
+        let timestamp = sqlite_timestamp(1714566600.75).unwrap();
+        assert_eq!(timestamp, "2024-05-01T12:30:00Z");
+
+        let parsed = chrono::DateTime::parse_from_rfc3339(&timestamp).unwrap();
+        assert_eq!(parsed.timestamp(), 1714566600);
+    }
+}
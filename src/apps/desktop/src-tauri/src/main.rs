@@ -1,53 +1,118 @@
+mod cache;
 mod db;
+mod diff;
 mod commands;
+mod embeddings;
+mod export;
+mod importer;
 mod session;
+mod settings;
+mod shortcuts;
+mod tray;
 mod ollama_api;
+mod window_state;
 
-use rusqlite::Connection;
-use tauri::{Manager, PhysicalPosition, PhysicalSize, WindowEvent};
+use tauri::{Manager, WindowEvent};
 use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+/// How long the window must stay still before its geometry is saved.
+const WINDOW_STATE_DEBOUNCE: Duration = Duration::from_millis(500);
 
-// Save window state
-fn save_window_state(window: &tauri::Window, conn: &Connection) -> rusqlite::Result<()> {
-    if let Ok(position) = window.outer_position() {
-        db::update_config_value(conn, "window_x", &position.x.to_string())?;
-        db::update_config_value(conn, "window_y", &position.y.to_string())?;
-    }
-
-    if let Ok(size) = window.outer_size() {
-        db::update_config_value(conn, "window_width", &size.width.to_string())?;
-        db::update_config_value(conn, "window_height", &size.height.to_string())?;
-    }
-
-    Ok(())
-}
 
-// Load window state
-fn load_window_state(window: &tauri::Window, conn: &Connection) -> rusqlite::Result<()> {
-    let x = db::get_config_value(conn, "window_x")?.unwrap_or_else(|| "100".to_string()).parse().unwrap_or(100);
-    let y = db::get_config_value(conn, "window_y")?.unwrap_or_else(|| "100".to_string()).parse().unwrap_or(100);
-    let width = db::get_config_value(conn, "window_width")?.unwrap_or_else(|| "1600".to_string()).parse().unwrap_or(800);
-    let height = db::get_config_value(conn, "window_height")?.unwrap_or_else(|| "1440".to_string()).parse().unwrap_or(600);
+// Tell the user why the database failed to open and, when a fresh database could help, offer to
+// move the broken one aside and start over. Exits when the database stays unavailable
+fn open_db_after_failure(error: db::DbInitError) -> db::DbPool {
+    use tauri::api::dialog::{blocking, MessageDialogKind};
 
-    window.set_position(tauri::Position::Physical(PhysicalPosition::new(x, y)))
-        .expect("Failed to set window position");
+    eprintln!("{}", error);
+    let show_error = |message: &str| {
+        blocking::MessageDialogBuilder::new("OllamaChat", message)
+            .kind(MessageDialogKind::Error)
+            .show();
+    };
 
-    window.set_size(PhysicalSize::new(width, height))
-        .expect("Failed to set window size");
+    let reset = error.can_reset()
+        && blocking::ask(
+            None::<&tauri::Window>,
+            "OllamaChat",
+            format!(
+                "{}\n\nReset the database? The current file is kept beside the new one, but the app will start without your chats.",
+                error
+            ),
+        );
+    if !reset {
+        show_error(&error.to_string());
+        std::process::exit(1);
+    }
 
-    Ok(())
+    let reopened = db::reset_database().and_then(|moved_to| {
+        println!("Moved the unreadable database to {}", moved_to.display());
+        db::init_db().map_err(|e| e.to_string())
+    });
+    match reopened {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("{}", e);
+            show_error(&e);
+            std::process::exit(1);
+        }
+    }
 }
 
 // application entry point
 fn main() {
-    let db_conn = db::init_db();
-    let generation_state = Arc::new(Mutex::new(session::GenerationState::default()));
+    let db_conn = match db::init_db() {
+        Ok(conn) => conn,
+        Err(e) => open_db_after_failure(e),
+    };
+    match db::checkout(&db_conn) {
+        Ok(conn) => {
+            settings::apply_runtime_config(&conn);
+
+            if db::get_config_value(&conn, settings::CLEANUP_ON_STARTUP_KEY).ok().flatten().as_deref() == Some("true") {
+                match settings::cleanup_config(&conn) {
+                    Ok(removed) if !removed.is_empty() => println!("Removed stale config keys: {}", removed.join(", ")),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Failed to clean up config: {}", e),
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to load startup config: {}", e),
+    }
+    let generation_state = Arc::new(Mutex::new(session::GenerationState {
+        current_session_id: Some(
+            db::checkout(&db_conn)
+                .and_then(|conn| db::last_session_id(&conn))
+                .unwrap_or_else(|e| {
+                    eprintln!("Failed to restore the last session: {}", e);
+                    -1
+                }),
+        ),
+        ..Default::default()
+    }));
+    let model_cache = Arc::new(Mutex::new(cache::ModelCache::default()));
+    let pull_state = Arc::new(Mutex::new(session::PullState::default()));
+    let embedding_scheduler = Arc::new(Mutex::new(embeddings::EmbeddingScheduler::default()));
+    let request_limiter = Arc::new(Mutex::new(session::RequestLimiter::new(
+        db::checkout(&db_conn)
+            .ok()
+            .and_then(|conn| db::get_config_value(&conn, "max_concurrency").ok().flatten())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(session::DEFAULT_MAX_CONCURRENCY),
+    )));
 
     tauri::Builder::default()
         .manage(db_conn.clone())
         .manage(generation_state)
+        .manage(model_cache)
+        .manage(request_limiter)
+        .manage(embedding_scheduler)
+        .manage(pull_state)
+        .system_tray(tray::system_tray())
+        .on_system_tray_event(|app, event| tray::handle_tray_event(app, event))
         .setup(move |app| {
             let window = app.get_window("main").unwrap();
 
@@ -55,21 +120,50 @@ fn main() {
             let window_clone_for_async = window.clone();
             let db_conn_clone_for_async = db_conn.clone();
             
-            tauri::async_runtime::block_on(async {
-                load_window_state(&window_clone_for_async, &*db_conn_clone_for_async.lock().await)
-                    .expect("Failed to load window state");
-            });
+            let conn = db::checkout(&db_conn_clone_for_async).expect("Failed to open the database");
+            shortcuts::register_saved_shortcuts(&app.handle(), &conn);
+            window_state::load_window_state(&window_clone_for_async, &conn)
+                .expect("Failed to load window state");
+            drop(conn);
         
             // Safe to use the original window and db_conn here
             let window_clone = window.clone();
             let db_conn_clone = db_conn.clone();
             
+            // Bumped on every resize/move; a pending save only runs if no newer event has arrived
+            let save_ticket = Arc::new(AtomicU64::new(0));
+
             window.on_window_event(move |event| {
+                if let WindowEvent::CloseRequested { api, .. } = event {
+                    // Capture the final geometry now rather than waiting on a pending debounced save
+                    save_ticket.fetch_add(1, Ordering::SeqCst);
+                    let saved = db::checkout(&db_conn_clone)
+                        .and_then(|conn| window_state::save_window_state(&window_clone, &conn));
+                    if let Err(e) = saved {
+                        eprintln!("Failed to save window state: {}", e);
+                    }
+
+                    // Hide to the tray instead of quitting when the user asked for it
+                    let to_tray = db::checkout(&db_conn_clone).map(|conn| tray::minimize_to_tray(&conn)).unwrap_or(false);
+                    if to_tray {
+                        api.prevent_close();
+                        let _ = window_clone.hide();
+                    }
+                }
                 if matches!(event, WindowEvent::Resized(_) | WindowEvent::Moved(_)) {
+                    let ticket = save_ticket.fetch_add(1, Ordering::SeqCst) + 1;
+                    let save_ticket_inner = save_ticket.clone();
                     let window_clone_inner = window_clone.clone();
                     let db_conn_clone_inner = db_conn_clone.clone();
                     tauri::async_runtime::spawn(async move {
-                        if let Err(e) = save_window_state(&window_clone_inner, &*db_conn_clone_inner.lock().await) {
+                        tokio::time::sleep(WINDOW_STATE_DEBOUNCE).await;
+                        // The window moved again since; that event's task saves instead
+                        if save_ticket_inner.load(Ordering::SeqCst) != ticket {
+                            return;
+                        }
+                        let saved = db::checkout(&db_conn_clone_inner)
+                            .and_then(|conn| window_state::save_window_state(&window_clone_inner, &conn));
+                        if let Err(e) = saved {
                             eprintln!("Failed to save window state: {}", e);
                         }
                     });
@@ -80,17 +174,109 @@ fn main() {
         })
         .invoke_handler(tauri::generate_handler![
             commands::load_models,
+            commands::check_model_supports,
+            commands::get_model_info,
+            commands::list_running_models,
+            commands::unload_model,
+            commands::pull_model,
+            commands::cancel_model_pull,
+            commands::delete_model,
+            commands::suggest_default_model,
+            commands::list_model_variants,
+            commands::fetch_models_by_recent_use,
             commands::get_selected_model,
             commands::save_selected_model,
+            commands::set_session_model,
+            commands::get_show_thinking,
+            commands::set_show_thinking,
+            commands::set_session_show_thinking,
             commands::clear_current_session,
             commands::get_current_session,
             commands::load_chat_history,
+            commands::get_session_stats,
+            commands::load_chat_history_page,
+            commands::fetch_chat_turns,
+            commands::add_manual_message,
+            commands::set_message_annotation,
+            commands::edit_message,
+            commands::delete_message,
+            commands::get_message_stats,
+            commands::export_metrics_csv,
+            commands::copy_session_to_clipboard,
+            commands::export_session_to_file,
+            commands::export_session_markdown,
+            commands::export_all_json,
+            commands::import_all_json,
+            commands::import_chatgpt_export,
+            commands::export_pair_jsonl,
+            commands::export_pairs_jsonl,
             commands::generate_chat,
+            commands::start_chat_generation,
+            commands::quick_chat,
+            commands::get_max_concurrency,
+            commands::set_max_concurrency,
+            commands::generate_candidates,
+            commands::accept_candidate,
+            commands::summarize_session,
+            commands::regenerate_session_title,
+            commands::replay_session,
+            commands::index_session_embeddings,
+            commands::semantic_search_sessions,
+            commands::search_messages,
+            commands::toggle_message_favorite,
+            commands::list_favorite_messages,
+            commands::get_session_config,
+            commands::set_session_system_prompt,
+            commands::set_session_context_budget,
+            commands::attach_file_to_prompt,
+            commands::list_personas,
+            commands::create_persona,
+            commands::update_persona,
+            commands::delete_persona,
+            commands::save_prompt_template,
+            commands::list_prompt_templates,
+            commands::update_prompt_template,
+            commands::delete_prompt_template,
+            commands::apply_persona,
+            commands::pin_session_model,
+            commands::check_session_model,
+            commands::predict_model_swap,
+            commands::get_settings,
+            commands::update_settings,
+            commands::set_config,
+            commands::cleanup_config,
+            commands::get_keep_alive_policy,
+            commands::set_keep_alive_policy,
+            commands::get_keep_alive,
+            commands::set_keep_alive,
+            commands::save_messages_bulk,
             commands::abort_generation,
+            commands::set_abort_hotkey,
+            commands::set_global_shortcut,
             commands::delete_chat_session,
+            commands::fork_session,
             commands::update_chat_session_name,
+            commands::toggle_session_pin,
             commands::load_chat_sessions,
-            commands::set_current_session
+            commands::set_session_archived,
+            commands::count_chat_sessions,
+            commands::clear_all_data,
+            commands::backup_database,
+            commands::restore_database,
+            commands::create_folder,
+            commands::rename_folder,
+            commands::delete_folder,
+            commands::load_folders,
+            commands::move_session_to_folder,
+            commands::sessions_using_model,
+            commands::fetch_audit_log,
+            commands::set_current_session,
+            commands::new_chat,
+            commands::reset_window_geometry,
+            commands::debug_generation_state,
+            commands::check_db_writable,
+            commands::get_data_dir,
+            commands::set_data_dir
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,17 +1,399 @@
 // Handles communication with external AI API (Ollama)
 
+use crate::cache::ModelCache;
 use crate::db;
+use crate::diff;
 use crate::commands::load_chat_history;
-use crate::session::GenerationState;
+use crate::embeddings::{self, EmbeddingScheduler};
+use crate::session::{self, GenerationRun, GenerationState, PullState, RequestLimiter};
+use crate::settings;
 
-use tauri::State;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_util::sync::CancellationToken;
+use tauri::{Manager, State, Window};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedSemaphorePermit};
 use reqwest::Client;
 use serde::Deserialize;
 use regex::Regex;
 use futures_util::StreamExt;
+use std::fmt;
+
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+
+// Base URL of the Ollama server, overridable through the `ollama_url` setting
+static OLLAMA_URL: RwLock<Option<String>> = RwLock::new(None);
+
+/// Points all API calls at `url`, or back at the default with `None`.
+pub fn set_ollama_url(url: Option<&str>) {
+    let url = url.map(|u| u.trim().trim_end_matches('/').to_string()).filter(|u| !u.is_empty());
+    *OLLAMA_URL.write().unwrap_or_else(|e| e.into_inner()) = url;
+}
+
+pub fn ollama_url() -> String {
+    OLLAMA_URL
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| DEFAULT_OLLAMA_URL.to_string())
+}
+
+fn api_url() -> String {
+    format!("{}/api", ollama_url())
+}
+
+/// Default seconds to wait for a connection to Ollama.
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+/// Default seconds a non-streaming request may take in total.
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 120;
+
+static CONNECT_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_CONNECT_TIMEOUT_SECS);
+static REQUEST_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_REQUEST_TIMEOUT_SECS);
+
+/// Sets the HTTP timeouts, falling back to the defaults for `None` or zero.
+pub fn set_timeouts(connect_secs: Option<u64>, request_secs: Option<u64>) {
+    let connect_secs = connect_secs.filter(|s| *s > 0).unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS);
+    let request_secs = request_secs.filter(|s| *s > 0).unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+    CONNECT_TIMEOUT_SECS.store(connect_secs, Ordering::Relaxed);
+    REQUEST_TIMEOUT_SECS.store(request_secs, Ordering::Relaxed);
+}
+
+fn connect_timeout() -> Duration {
+    Duration::from_secs(CONNECT_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+/// Default number of times a request is retried after a connection error or 5xx response.
+pub const DEFAULT_MAX_RETRIES: u64 = 2;
+// Delay before the first retry; it doubles for each further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+static MAX_RETRIES: AtomicU64 = AtomicU64::new(DEFAULT_MAX_RETRIES);
+
+/// Sets how many times transient failures are retried, falling back to the default for `None`.
+pub fn set_max_retries(retries: Option<u64>) {
+    MAX_RETRIES.store(retries.unwrap_or(DEFAULT_MAX_RETRIES), Ordering::Relaxed);
+}
+
+// Send the request built by `build`, retrying with growing delays while Ollama can't be reached or
+// answers 5xx, e.g. just after loading a model. 4xx responses and the last attempt's outcome are
+// returned as they are, so callers keep their own status handling
+async fn retry_with_backoff<F>(build: F) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let max_retries = MAX_RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        let result = build().send().await;
+        let transient = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(e) => e.is_connect(),
+        };
+        if !transient || attempt >= max_retries {
+            return result;
+        }
+        tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt.min(6) as u32)).await;
+        attempt += 1;
+    }
+}
+
+// Client for short requests such as listing or inspecting models
+fn client() -> Client {
+    Client::builder()
+        .connect_timeout(connect_timeout())
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS.load(Ordering::Relaxed)))
+        .build()
+        .unwrap_or_default()
+}
+
+// Client for generations, which may legitimately run for minutes
+fn generation_client() -> Client {
+    Client::builder()
+        .connect_timeout(connect_timeout())
+        .build()
+        .unwrap_or_default()
+}
+
+/// Transport-level failures talking to Ollama, converted to user-facing messages by `Display`.
+#[derive(Debug)]
+pub enum OllamaError {
+    ConnectionRefused,
+    Timeout,
+    HttpStatus(u16),
+    Decode(String),
+    Request(String),
+}
+
+impl fmt::Display for OllamaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OllamaError::ConnectionRefused => {
+                write!(f, "Ollama is not running at {}. Start Ollama and try again.", ollama_url())
+            }
+            OllamaError::Timeout => write!(f, "Ollama did not respond in time"),
+            OllamaError::HttpStatus(404) => write!(f, "Model not found (HTTP 404)"),
+            OllamaError::HttpStatus(status) => write!(f, "Ollama returned HTTP {}", status),
+            OllamaError::Decode(message) => write!(f, "Unexpected response from Ollama: {}", message),
+            OllamaError::Request(message) => write!(f, "Request to Ollama failed: {}", message),
+        }
+    }
+}
+
+// Read the message of a failed response: Ollama's `{"error": "..."}` body when present, else the status
+async fn response_error(response: reqwest::Response) -> String {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    body_error(status, &body)
+}
+
+// Message of a failed response from its status and body, see `response_error`
+fn body_error(status: reqwest::StatusCode, body: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|json| json["error"].as_str().map(|e| e.to_string()))
+        .or_else(|| Some(body.trim().to_string()).filter(|b| !b.is_empty()))
+        .unwrap_or_else(|| format!("API call failed with status: {}", status))
+}
+
+impl From<reqwest::Error> for OllamaError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            OllamaError::ConnectionRefused
+        } else if e.is_timeout() {
+            OllamaError::Timeout
+        } else if let Some(status) = e.status() {
+            OllamaError::HttpStatus(status.as_u16())
+        } else if e.is_decode() {
+            OllamaError::Decode(e.to_string())
+        } else {
+            OllamaError::Request(e.to_string())
+        }
+    }
+}
+
+impl From<OllamaError> for String {
+    fn from(e: OllamaError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<OllamaError> for GenerationError {
+    fn from(e: OllamaError) -> Self {
+        GenerationError::Failed(e.to_string())
+    }
+}
+
+/// Token counts and timings (in nanoseconds) from the final chunk of a generation.
+#[derive(Debug, Default, Clone, Deserialize, serde::Serialize)]
+pub struct GenerationStats {
+    pub prompt_eval_count: Option<i64>,
+    pub eval_count: Option<i64>,
+    pub prompt_eval_duration: Option<i64>,
+    pub eval_duration: Option<i64>,
+    pub total_duration: Option<i64>,
+    /// Seed the reply was sampled with, when one was requested.
+    #[serde(default)]
+    pub seed: Option<i64>,
+    /// Temperature the reply was sampled with, when one was requested.
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    /// Context window the reply was generated with, when one was requested.
+    #[serde(default)]
+    pub num_ctx: Option<i64>,
+    /// Generation speed, derived from `eval_count` and `eval_duration`.
+    #[serde(default, skip_deserializing)]
+    pub tokens_per_second: Option<f64>,
+}
+
+impl GenerationStats {
+    /// Fills in `tokens_per_second` as `eval_count / (eval_duration / 1e9)`.
+    pub fn with_rate(mut self) -> Self {
+        self.tokens_per_second = match (self.eval_count, self.eval_duration) {
+            (Some(count), Some(duration)) if duration > 0 => Some(count as f64 / (duration as f64 / 1e9)),
+            _ => None,
+        };
+        self
+    }
+}
+
+/// Errors from chat generation, tagged by kind so the UI can react to specific failures.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum GenerationError {
+    /// The model's chat template failed to render; raw generate mode may still work.
+    TemplateError(String),
+    /// Ollama reported an error after streaming had started (e.g. out of memory);
+    /// the partial reply has been saved.
+    StreamError(String),
+    /// Another generation is running; the request was rejected without touching it.
+    AlreadyRunning,
+    /// The requested model isn't installed in Ollama, e.g. it was deleted after being selected.
+    ModelNotAvailable { model: String, available: Vec<String> },
+    Failed(String),
+}
+
+impl fmt::Display for GenerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerationError::TemplateError(message) => write!(f, "Template error: {}", message),
+            GenerationError::StreamError(message) => write!(f, "Ollama error during generation: {}", message),
+            GenerationError::AlreadyRunning => write!(f, "{}", session::ALREADY_GENERATING),
+            GenerationError::ModelNotAvailable { model, available } if available.is_empty() => {
+                write!(f, "Model {} is not installed and no models are available", model)
+            }
+            GenerationError::ModelNotAvailable { model, available } => {
+                write!(f, "Model {} is not installed. Available models: {}", model, available.join(", "))
+            }
+            GenerationError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for GenerationError {
+    fn from(message: String) -> Self {
+        GenerationError::Failed(message)
+    }
+}
+
+// Ollama reports broken model templates with Go's text/template error prefix
+fn is_template_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("template:") || message.contains("error rendering template")
+}
+
+/// Sampling parameters forwarded in the `options` object of an Ollama request.
+/// Unset fields are omitted so Ollama falls back to the model defaults.
+#[derive(Debug, Default, Clone, Deserialize, serde::Serialize)]
+pub struct GenerationOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Context window size in tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<i64>,
+    /// Sequences that end the reply when generated; the reply is saved without them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Output format: `"json"` or a JSON schema object. Sent as the top-level `format`
+    /// of the request rather than inside `options`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+}
+
+/// Checks that a `format` is either `"json"` or a JSON schema object.
+pub fn check_format(format: &serde_json::Value) -> Result<(), String> {
+    match format {
+        serde_json::Value::String(f) if f == "json" => Ok(()),
+        serde_json::Value::Object(_) => Ok(()),
+        other => Err(format!("Invalid format {}: expected \"json\" or a JSON schema object", other)),
+    }
+}
+
+/// Moves a history message's `attachments` into its content, each file ahead of the prompt
+/// under a header naming it, so the model sees the files the prompt refers to.
+fn inline_attachments(message: &mut serde_json::Value) {
+    let Some(attachments) = message.as_object_mut().and_then(|m| m.remove("attachments")) else {
+        return;
+    };
+    let mut content = String::new();
+    for attachment in attachments.as_array().into_iter().flatten() {
+        let file_name = attachment["file_name"].as_str().unwrap_or("attachment");
+        let text = attachment["content"].as_str().unwrap_or_default();
+        content.push_str(&format!(
+            "----- BEGIN FILE: {} -----\n{}\n----- END FILE: {} -----\n\n",
+            file_name,
+            text.trim_end(),
+            file_name
+        ));
+    }
+    content.push_str(message["content"].as_str().unwrap_or_default());
+    message["content"] = serde_json::Value::String(content);
+}
+
+// Decode the first `len` bytes of base64 data, enough to sniff a file signature
+fn base64_prefix(data: &str, len: usize) -> Vec<u8> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+    let mut bytes = Vec::with_capacity(len);
+    let (mut acc, mut bits) = (0u32, 0);
+    for sextet in data.bytes().map_while(value) {
+        acc = (acc << 6) | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+            if bytes.len() == len {
+                break;
+            }
+        }
+    }
+    bytes
+}
+
+/// Validates a base64 image (optionally a `data:image/...;base64,` URL) and returns the bare
+/// base64 Ollama expects. Only PNG, JPEG, GIF, WebP and BMP data is accepted.
+pub fn normalize_image(data: &str) -> Result<String, String> {
+    let data = data.trim();
+    let data = match data.strip_prefix("data:") {
+        Some(url) => {
+            let (media_type, payload) = url
+                .split_once(";base64,")
+                .ok_or_else(|| "Image data URLs must be base64 encoded".to_string())?;
+            if !media_type.starts_with("image/") {
+                return Err(format!("Unsupported attachment type '{}': only images are accepted", media_type));
+            }
+            payload
+        }
+        None => data,
+    };
+
+    let header = base64_prefix(data, 12);
+    let is_image = header.starts_with(b"\x89PNG")
+        || header.starts_with(&[0xFF, 0xD8, 0xFF])
+        || header.starts_with(b"GIF8")
+        || (header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP".as_slice()))
+        || header.starts_with(b"BM");
+    if !is_image {
+        return Err("Unsupported attachment: expected a PNG, JPEG, GIF, WebP or BMP image".to_string());
+    }
+    Ok(data.to_string())
+}
+
+// Put `options` into a request body, moving `format` to the top level where Ollama expects it
+fn apply_options(request_body: &mut serde_json::Value, options: &GenerationOptions) {
+    let mut options_json = serde_json::json!(options);
+    if let Some(format) = options_json.as_object_mut().and_then(|o| o.remove("format")) {
+        request_body["format"] = format;
+    }
+    request_body["options"] = options_json;
+}
+
+/// Maximum number of candidates `generate_candidates` runs at once.
+pub const MAX_CANDIDATES: usize = 5;
+
+/// One sampled response from `generate_candidates`.
+#[derive(Debug, serde::Serialize)]
+pub struct Candidate {
+    pub seed: i64,
+    pub content: String,
+    pub error: Option<String>,
+}
 
 #[derive(Deserialize)]
 pub struct AIResponse {
@@ -20,162 +402,1398 @@ pub struct AIResponse {
 }
 
 pub async fn fetch_models() -> Result<Vec<String>, String> {
-    let client = Client::new();
-    let res = client.get("http://localhost:11434/api/tags").send().await;
-
-    match res {
-        Ok(response) => {
-            let data: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
-            let models = data["models"]
-                .as_array()
-                .unwrap()
+    let client = client();
+    let response = retry_with_backoff(|| client.get(format!("{}/tags", api_url())))
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(OllamaError::from)?;
+
+    let data: serde_json::Value = response.json().await.map_err(OllamaError::from)?;
+    let models = data["models"]
+        .as_array()
+        .ok_or_else(|| OllamaError::Decode("missing model list".to_string()))?
+        .iter()
+        .filter_map(|m| m["name"].as_str().map(|s| s.to_string()))
+        .collect();
+    Ok(models)
+}
+
+// The installed models, from the cache while it is fresh unless `force` asks for a refetch
+pub async fn fetch_models_cached(cache: &Arc<Mutex<ModelCache>>, force: bool) -> Result<Vec<String>, String> {
+    if !force {
+        if let Some(models) = cache.lock().await.fresh_models() {
+            return Ok(models.clone());
+        }
+    }
+    let models = fetch_models().await?;
+    cache.lock().await.models = Some((std::time::Instant::now(), models.clone()));
+    Ok(models)
+}
+
+/// Metadata for a family/size/quantization of an installed model.
+#[derive(Debug, Default, Clone, Deserialize, serde::Serialize)]
+pub struct ModelDetailsInfo {
+    #[serde(default)]
+    pub family: String,
+    #[serde(default)]
+    pub parameter_size: String,
+    #[serde(default)]
+    pub quantization_level: String,
+}
+
+/// An installed model as listed by `/api/tags`.
+#[derive(Debug, Clone, Deserialize, serde::Serialize)]
+pub struct ModelDetails {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub modified_at: String,
+    #[serde(default)]
+    pub details: ModelDetailsInfo,
+}
+
+// Fetch the installed models together with their size, digest and details
+pub async fn fetch_models_detailed() -> Result<Vec<ModelDetails>, String> {
+    let client = client();
+    let response = retry_with_backoff(|| client.get(format!("{}/tags", api_url())))
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(OllamaError::from)?;
+
+    let data: serde_json::Value = response.json().await.map_err(OllamaError::from)?;
+    serde_json::from_value(data["models"].clone()).map_err(|e| e.to_string())
+}
+
+/// One installed tag of a base model.
+#[derive(Debug, serde::Serialize)]
+pub struct ModelVariant {
+    pub name: String,
+    pub tag: String,
+    pub parameter_size: String,
+    pub quantization_level: String,
+    pub size: u64,
+}
+
+/// Installed variants sharing a base model name, smallest first.
+#[derive(Debug, serde::Serialize)]
+pub struct ModelVariantGroup {
+    pub base: String,
+    pub variants: Vec<ModelVariant>,
+}
+
+// Split a model name such as `llama3.1:8b-instruct-q4_K_M` into its base name and tag
+fn split_model_tag(name: &str) -> (&str, &str) {
+    name.rsplit_once(':').unwrap_or((name, "latest"))
+}
+
+// Group installed models by base name, keeping those matching `family` by base name or reported family
+pub fn group_model_variants(models: Vec<ModelDetails>, family: Option<&str>) -> Vec<ModelVariantGroup> {
+    let family = family.map(|f| f.trim().to_lowercase()).filter(|f| !f.is_empty());
+    let mut groups: Vec<ModelVariantGroup> = Vec::new();
+
+    for model in models {
+        let (base, tag) = split_model_tag(&model.name);
+        if let Some(family) = &family {
+            let base_name = base.rsplit('/').next().unwrap_or(base).to_lowercase();
+            if !base_name.starts_with(family.as_str()) && model.details.family.to_lowercase() != *family {
+                continue;
+            }
+        }
+
+        let variant = ModelVariant {
+            name: model.name.clone(),
+            tag: tag.to_string(),
+            parameter_size: model.details.parameter_size.clone(),
+            quantization_level: model.details.quantization_level.clone(),
+            size: model.size,
+        };
+        match groups.iter_mut().find(|g| g.base == base) {
+            Some(group) => group.variants.push(variant),
+            None => groups.push(ModelVariantGroup { base: base.to_string(), variants: vec![variant] }),
+        }
+    }
+
+    for group in &mut groups {
+        group.variants.sort_by_key(|v| v.size);
+    }
+    groups.sort_by(|a, b| a.base.cmp(&b.base));
+    groups
+}
+
+// List installed models, most recently used in the app first; never-used models keep Ollama's order at the end
+pub async fn fetch_models_by_recent_use(
+    db_conn: &db::DbPool,
+) -> Result<Vec<String>, String> {
+    let mut models = fetch_models().await?;
+    let last_used = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        db::fetch_model_last_used(&conn).map_err(|e| e.to_string())?
+    };
+
+    // Stable sort: used models by descending timestamp, unused ones after
+    models.sort_by(|a, b| match (last_used.get(a), last_used.get(b)) {
+        (Some(a), Some(b)) => b.cmp(a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+
+    Ok(models)
+}
+
+// Pick a sensible default model: the smallest installed instruct/chat model,
+// otherwise the most recently modified one. Embedding-only models are never suggested.
+pub fn pick_default_model(models: &[ModelDetails]) -> Option<String> {
+    let candidates: Vec<&ModelDetails> = models
+        .iter()
+        .filter(|m| !m.name.to_lowercase().contains("embed"))
+        .collect();
+
+    candidates
+        .iter()
+        .filter(|m| {
+            let name = m.name.to_lowercase();
+            name.contains("instruct") || name.contains("chat")
+        })
+        .min_by_key(|m| m.size)
+        .or_else(|| candidates.iter().max_by(|a, b| a.modified_at.cmp(&b.modified_at)))
+        .map(|m| m.name.clone())
+}
+
+// Resolve a model tag to the digest of the locally installed version.
+// `/api/show` doesn't report the digest, so it is looked up in `/api/tags`.
+pub async fn resolve_model_digest(model: &str) -> Result<String, String> {
+    fetch_models_detailed()
+        .await?
+        .into_iter()
+        .find(|m| same_model(&m.name, model))
+        .map(|m| m.digest)
+        .ok_or_else(|| format!("Model {} is not installed", model))
+}
+
+/// Payload of the `model-changed` event, emitted when a session's pinned digest no longer matches.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModelChangedWarning {
+    pub session_id: i64,
+    pub model: String,
+    pub pinned_digest: String,
+    pub current_digest: String,
+}
+
+// Compare a session's pinned model digest with the one currently installed
+pub async fn check_session_model_digest(
+    session_id: i64,
+    db_conn: &db::DbPool,
+) -> Result<Option<ModelChangedWarning>, String> {
+    let pin = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        db::get_session_model_pin(&conn, session_id).map_err(|e| e.to_string())?
+    };
+
+    let Some((model, pinned_digest)) = pin else {
+        return Ok(None);
+    };
+
+    let current_digest = resolve_model_digest(&model).await?;
+    if current_digest == pinned_digest {
+        return Ok(None);
+    }
+
+    Ok(Some(ModelChangedWarning {
+        session_id,
+        model,
+        pinned_digest,
+        current_digest,
+    }))
+}
+
+/// A model currently loaded in memory, as listed by `/api/ps`.
+#[derive(Debug, Clone, Deserialize, serde::Serialize)]
+pub struct RunningModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub size_vram: u64,
+    #[serde(default)]
+    pub digest: String,
+    /// When Ollama unloads the model, as RFC 3339 in UTC; far in the future for `keep_alive: -1`.
+    #[serde(default)]
+    pub expires_at: String,
+    /// Seconds until the model is unloaded, derived from `expires_at`.
+    #[serde(default, skip_deserializing)]
+    pub expires_in_secs: Option<i64>,
+}
+
+impl RunningModel {
+    // Ollama reports `expires_at` in local time with nanoseconds, e.g. "2024-06-04T14:38:31.83753-07:00"
+    fn with_expiry(mut self) -> Self {
+        if let Ok(expires_at) = chrono::DateTime::parse_from_rfc3339(&self.expires_at) {
+            let expires_at = expires_at.with_timezone(&chrono::Utc);
+            self.expires_in_secs = Some((expires_at - chrono::Utc::now()).num_seconds().max(0));
+            self.expires_at = expires_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        }
+        self
+    }
+}
+
+// Fetch the models Ollama currently has loaded
+pub async fn fetch_running_models() -> Result<Vec<RunningModel>, String> {
+    let client = client();
+    let response = client
+        .get(format!("{}/ps", api_url()))
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to list running models: status {}", response.status()));
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+    let models: Vec<RunningModel> = serde_json::from_value(data["models"].clone()).map_err(|e| e.to_string())?;
+    Ok(models.into_iter().map(RunningModel::with_expiry).collect())
+}
+
+// Unload a model from memory now by sending an empty generate request with `keep_alive: 0`.
+// The empty prompt makes Ollama only load/unload; it answers `done` once the model is evicted
+pub async fn unload_model(model_name: &str) -> Result<(), String> {
+    let response = client()
+        .post(format!("{}/generate", api_url()))
+        .json(&serde_json::json!({ "model": model_name, "prompt": "", "stream": false, "keep_alive": 0 }))
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to unload {}: {}", model_name, response_error(response).await));
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(OllamaError::from)?;
+    if data["done"].as_bool() != Some(true) {
+        return Err(format!("Ollama did not confirm unloading {}", model_name));
+    }
+    Ok(())
+}
+
+/// Payload of the `model-pull-progress` event, emitted for every status line of a pull.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModelPullProgress {
+    pub model: String,
+    pub status: String,
+    /// Bytes downloaded and total bytes of the layer being pulled, when reported.
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+    pub percent: Option<f64>,
+}
+
+/// Payload of the `model-pull-done` event, emitted once a pull has ended.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModelPullDonePayload {
+    pub model: String,
+    /// Ollama reported `{"status": "success"}`.
+    pub success: bool,
+    pub cancelled: bool,
+    pub error: Option<String>,
+}
+
+// Download a model through /api/pull, emitting progress events until it completes or is cancelled
+pub async fn pull_model(
+    model_name: &str,
+    window: &Window,
+    pulls: &Arc<Mutex<PullState>>,
+) -> Result<(), String> {
+    let cancellation_token = pulls
+        .lock()
+        .await
+        .begin(model_name)
+        .ok_or_else(|| format!("{} is already being pulled", model_name))?;
+
+    let result = tokio::select! {
+        result = stream_model_pull(model_name, window) => result,
+        _ = cancellation_token.cancelled() => Err(format!("Pull of {} was cancelled", model_name)),
+    };
+    let cancelled = cancellation_token.is_cancelled();
+    if !cancelled {
+        pulls.lock().await.finish(model_name);
+    }
+
+    let _ = window.emit("model-pull-done", ModelPullDonePayload {
+        model: model_name.to_string(),
+        success: result.is_ok(),
+        cancelled,
+        error: result.as_ref().err().cloned(),
+    });
+    result
+}
+
+async fn stream_model_pull(model_name: &str, window: &Window) -> Result<(), String> {
+    let response = generation_client()
+        .post(format!("{}/pull", api_url()))
+        .json(&serde_json::json!({ "model": model_name, "stream": true }))
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(response_error(response).await);
+    }
+
+    let mut stream = response.bytes_stream();
+    // Network chunks don't align with Ollama's JSON lines, so buffer until a newline
+    let mut buffer = String::new();
+    while let Some(chunk) = stream.next().await {
+        let data = chunk.map_err(|e| e.to_string())?;
+        buffer.push_str(&String::from_utf8_lossy(&data));
+
+        while let Some(newline) = buffer.find('\n') {
+            let line: String = buffer.drain(..=newline).collect();
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(line.trim()) else {
+                continue;
+            };
+
+            if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
+                return Err(error.to_string());
+            }
+            let status = json.get("status").and_then(|s| s.as_str()).unwrap_or_default();
+            if status == "success" {
+                return Ok(());
+            }
+
+            let completed = json.get("completed").and_then(|c| c.as_u64());
+            let total = json.get("total").and_then(|t| t.as_u64());
+            let percent = match (completed, total) {
+                (Some(completed), Some(total)) if total > 0 => Some(completed as f64 / total as f64 * 100.0),
+                _ => None,
+            };
+            let _ = window.emit("model-pull-progress", ModelPullProgress {
+                model: model_name.to_string(),
+                status: status.to_string(),
+                completed,
+                total,
+                percent,
+            });
+        }
+    }
+
+    Err(format!("Pull of {} ended before completing", model_name))
+}
+
+// Remove an installed model through /api/delete
+pub async fn delete_model(model_name: &str) -> Result<(), String> {
+    client()
+        .delete(format!("{}/delete", api_url()))
+        .json(&serde_json::json!({ "name": model_name }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(OllamaError::from)?;
+    Ok(())
+}
+
+// Rough disk read speed used to estimate how long loading a model takes
+const MODEL_LOAD_BYTES_PER_SEC: f64 = 1.5e9;
+
+/// Whether generating in a session will first have to load its model.
+#[derive(Debug, serde::Serialize)]
+pub struct ModelSwapPrediction {
+    pub model: String,
+    pub loaded: bool,
+    /// Another model is loaded and will likely be evicted.
+    pub swap_needed: bool,
+    pub loaded_models: Vec<String>,
+    /// Rough load time in seconds, when the model's size is known.
+    pub estimated_load_seconds: Option<f64>,
+    /// "none", "load" or "swap".
+    pub cost_hint: String,
+}
+
+// Predict whether the session's model must be loaded, and whether that evicts another model
+pub async fn predict_model_swap(
+    session_id: i64,
+    db_conn: &db::DbPool,
+) -> Result<ModelSwapPrediction, String> {
+    let model = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        let session_model = db::get_session_config(&conn, session_id).map_err(|e| e.to_string())?.model;
+        match session_model {
+            Some(model) => model,
+            None => db::get_config_value(&conn, "selected_model_name")
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("Session {} has no model and none is selected", session_id))?,
+        }
+    };
+
+    let running = fetch_running_models().await?;
+    let loaded = running.iter().any(|m| same_model(&m.name, &model));
+    let swap_needed = !loaded && !running.is_empty();
+
+    let estimated_load_seconds = if loaded {
+        None
+    } else {
+        fetch_models_detailed()
+            .await?
+            .into_iter()
+            .find(|m| same_model(&m.name, &model))
+            .map(|m| (m.size as f64 / MODEL_LOAD_BYTES_PER_SEC * 10.0).round() / 10.0)
+    };
+
+    let cost_hint = match (loaded, swap_needed) {
+        (true, _) => "none",
+        (false, true) => "swap",
+        (false, false) => "load",
+    };
+
+    Ok(ModelSwapPrediction {
+        model,
+        loaded,
+        swap_needed,
+        loaded_models: running.into_iter().map(|m| m.name).collect(),
+        estimated_load_seconds,
+        cost_hint: cost_hint.to_string(),
+    })
+}
+
+/// Result of checking a model against a set of requested features.
+#[derive(Debug, serde::Serialize)]
+pub struct ModelSupport {
+    pub model: String,
+    pub supported: Vec<String>,
+    pub unsupported: Vec<String>,
+}
+
+// Map a requested feature name onto the capability name Ollama reports
+fn capability_for_feature(feature: &str) -> String {
+    match feature.to_lowercase().as_str() {
+        "images" | "vision" => "vision".to_string(),
+        "embeddings" | "embedding" | "embed" => "embedding".to_string(),
+        "tools" | "tool" => "tools".to_string(),
+        other => other.to_string(),
+    }
+}
+
+// Fetch the capability list of a model from /api/show
+pub async fn fetch_model_capabilities(model: &str) -> Result<Vec<String>, String> {
+    let client = client();
+
+    let response = client
+        .post(format!("{}/show", api_url()))
+        .json(&serde_json::json!({ "model": model }))
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to show model {}: {}", model, response_error(response).await));
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+
+    if let Some(capabilities) = data["capabilities"].as_array() {
+        return Ok(capabilities
+            .iter()
+            .filter_map(|c| c.as_str().map(|s| s.to_string()))
+            .collect());
+    }
+
+    // Older Ollama versions don't report capabilities, so infer them from the model metadata
+    let mut capabilities = vec!["completion".to_string()];
+    let has_projector = data.get("projector_info").is_some()
+        || data["details"]["families"]
+            .as_array()
+            .map(|families| families.iter().any(|f| f.as_str() == Some("clip")))
+            .unwrap_or(false);
+    if has_projector {
+        capabilities.push("vision".to_string());
+    }
+    if data["template"].as_str().map(|t| t.contains(".Tools")).unwrap_or(false) {
+        capabilities.push("tools".to_string());
+    }
+
+    Ok(capabilities)
+}
+
+/// What `/api/show` reports about an installed model.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModelInfo {
+    pub model: String,
+    /// Default parameters from the Modelfile, one `name value` pair per line.
+    pub parameters: Option<String>,
+    pub template: Option<String>,
+    pub details: ModelDetailsInfo,
+    /// Longest context the model was trained for, in tokens; a ceiling for `num_ctx`.
+    pub context_length: Option<u64>,
+    /// The full Modelfile, which can be large; only included on request.
+    pub modelfile: Option<String>,
+}
+
+// Fetch a model's parameters, template, details and context length from /api/show
+pub async fn fetch_model_info(model: &str) -> Result<ModelInfo, String> {
+    let response = client()
+        .post(format!("{}/show", api_url()))
+        .json(&serde_json::json!({ "model": model }))
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to show model {}: {}", model, response_error(response).await));
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+    let text = |field: &str| data[field].as_str().map(|s| s.to_string());
+
+    // Keyed by architecture, e.g. `llama.context_length`
+    let model_info = &data["model_info"];
+    let context_length = model_info["general.architecture"]
+        .as_str()
+        .and_then(|arch| model_info[format!("{}.context_length", arch)].as_u64())
+        .or_else(|| {
+            model_info
+                .as_object()?
                 .iter()
-                .filter_map(|m| m["name"].as_str().map(|s| s.to_string()))
-                .collect();
-            Ok(models)
+                .find(|(key, _)| key.ends_with(".context_length"))
+                .and_then(|(_, value)| value.as_u64())
+        });
+
+    Ok(ModelInfo {
+        model: model.to_string(),
+        parameters: text("parameters"),
+        template: text("template"),
+        details: serde_json::from_value(data["details"].clone()).unwrap_or_default(),
+        context_length,
+        modelfile: text("modelfile"),
+    })
+}
+
+// Look up a model's info, caching it until the model is pulled again or deleted
+pub async fn get_model_info(
+    model: &str,
+    include_modelfile: bool,
+    cache: &Arc<Mutex<ModelCache>>,
+) -> Result<ModelInfo, String> {
+    let cached = cache.lock().await.info.get(model).cloned();
+    let mut info = match cached {
+        Some(info) => info,
+        None => {
+            let info = fetch_model_info(model).await?;
+            cache.lock().await.info.insert(model.to_string(), info.clone());
+            info
+        }
+    };
+    if !include_modelfile {
+        info.modelfile = None;
+    }
+    Ok(info)
+}
+
+// Compute the embedding of a text with /api/embed
+pub async fn fetch_embedding(model: &str, input: &str) -> Result<Vec<f32>, String> {
+    #[derive(Deserialize)]
+    struct EmbedResponse {
+        embeddings: Vec<Vec<f32>>,
+    }
+
+    let client = client();
+
+    let response = client
+        .post(format!("{}/embed", api_url()))
+        .json(&serde_json::json!({ "model": model, "input": input }))
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    // A missing model is the usual failure; say how to fix it rather than echoing the 404
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(format!(
+            "Embedding model {} is not available; pull it or choose another embedding model",
+            model
+        ));
+    }
+    if !response.status().is_success() {
+        return Err(format!("Failed to embed with {}: {}", model, response_error(response).await));
+    }
+
+    let data: EmbedResponse = response.json().await.map_err(|e| e.to_string())?;
+    data.embeddings
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("Model {} returned no embedding", model))
+}
+
+// Ollama resolves a bare name to its `:latest` tag, so "llama3" and "llama3:latest" are the same model
+fn same_model(a: &str, b: &str) -> bool {
+    a.strip_suffix(":latest").unwrap_or(a) == b.strip_suffix(":latest").unwrap_or(b)
+}
+
+// Fail with `ModelNotAvailable` when `model` isn't installed, using a briefly cached model list.
+// An unreachable Ollama is not reported here; the request itself will surface that
+async fn ensure_model_available(model: &str, cache: &Arc<Mutex<ModelCache>>) -> Result<(), GenerationError> {
+    let Ok(models) = fetch_models_cached(cache, false).await else {
+        return Ok(());
+    };
+
+    if models.iter().any(|m| same_model(m, model)) {
+        Ok(())
+    } else {
+        Err(GenerationError::ModelNotAvailable { model: model.to_string(), available: models })
+    }
+}
+
+// Check which of the requested features a model supports, caching capabilities per model
+pub async fn check_model_supports(
+    model: &str,
+    features: &[String],
+    cache: &Arc<Mutex<ModelCache>>,
+) -> Result<ModelSupport, String> {
+    let cached = cache.lock().await.capabilities.get(model).cloned();
+
+    let capabilities = match cached {
+        Some(capabilities) => capabilities,
+        None => {
+            let capabilities = fetch_model_capabilities(model).await?;
+            cache
+                .lock()
+                .await
+                .capabilities
+                .insert(model.to_string(), capabilities.clone());
+            capabilities
         }
-        Err(_) => Err("Failed to load models".into()),
+    };
+
+    let (supported, unsupported) = features
+        .iter()
+        .cloned()
+        .partition(|feature| capabilities.contains(&capability_for_feature(feature)));
+
+    Ok(ModelSupport {
+        model: model.to_string(),
+        supported,
+        unsupported,
+    })
+}
+
+// Remove a leading <think>...</think> reasoning block from model output
+pub fn strip_think(text: &str) -> String {
+    let re_think = Regex::new(r"(?s)^\s*<think>.*?</think>(\s*)").unwrap();
+    re_think.replace(text, "").to_string()
+}
+
+/// Validates a keep_alive value ("5m", "1h30m", "0", "-1") and converts it to the JSON Ollama expects.
+/// Bare numbers are sent as seconds, since Ollama rejects unit-less duration strings like "-1".
+pub fn parse_keep_alive(value: &str) -> Result<serde_json::Value, String> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Ok(serde_json::json!(seconds));
+    }
+
+    let re_duration = Regex::new(r"^-?(\d+(\.\d+)?(ns|us|µs|ms|s|m|h))+$").unwrap();
+    if re_duration.is_match(value) {
+        Ok(serde_json::json!(value))
+    } else {
+        Err(format!(
+            "Invalid keep_alive value '{}': expected a duration like \"5m\" or a number of seconds like \"0\" or \"-1\"",
+            value
+        ))
     }
 }
 
+// Resolve the keep_alive to send: a per-call override wins over the configured policy
+pub async fn resolve_keep_alive(
+    override_value: Option<String>,
+    db_conn: &db::DbPool,
+) -> Result<Option<serde_json::Value>, String> {
+    let value = match override_value {
+        Some(value) => Some(value),
+        None => {
+            let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+            db::get_config_value(&conn, "keep_alive").map_err(|e| e.to_string())?
+        }
+    };
+
+    value
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| parse_keep_alive(&v))
+        .transpose()
+}
+
+// Generate a chat session title
+pub async fn generate_session_title_with_ai(
+    exchange: &str,
+    model: &str,
+    keep_alive: Option<&serde_json::Value>,
+) -> Result<String, String> {
+    let client = client();
+
+    let mut request_body = serde_json::json!({
+        "model": model,
+        "prompt": format!(
+            "Generate a concise and informative title (at most 10 words) summarizing the conversation. 
+            Respond with only the title as plain text. Do not include any explanations, formatting, 
+            or additional content. The conversation to summarize is: ```{}```",
+            exchange
+        )
+    });
+    if let Some(keep_alive) = keep_alive {
+        request_body["keep_alive"] = keep_alive.clone();
+    }
+
+    let response = retry_with_backoff(|| {
+        client
+            .post(format!("{}/generate", api_url()))
+            .header("Content-Type", "application/json")
+            .json(&request_body)
+    })
+    .await
+    .and_then(|r| r.error_for_status())
+    .map_err(OllamaError::from)?;
+
+    let mut full_response = String::new();
+
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk_result) = stream.next().await {
+        match chunk_result {
+            Ok(bytes) => {
+                let text = String::from_utf8_lossy(&bytes);
+                for line in text.lines() {
+                    if let Ok(parsed) = serde_json::from_str::<AIResponse>(line) {
+                        full_response.push_str(&parsed.response);
+                        if parsed.done {
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                return Err(format!("Error reading stream: {}", e));
+            }
+        }
+    }
+
+    let title = strip_think(&full_response).trim().trim_matches('"').trim_matches('*').trim().to_string();
+    if title.is_empty() {
+        return Err(format!("Model {} returned an empty title", model));
+    }
+
+    Ok(title)
+}
+
+// Length of the prompt-based title a new session gets until the model titles it
+const FALLBACK_TITLE_CHARS: usize = 50;
+
+/// Title of a session created before its first message.
+pub const NEW_CHAT_TITLE: &str = "New chat";
+
+/// Title made from the start of the prompt's first line, kept when AI titling fails.
+pub fn fallback_title(prompt: &str) -> String {
+    let line = prompt.trim().lines().next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return NEW_CHAT_TITLE.to_string();
+    }
+    let mut title: String = line.chars().take(FALLBACK_TITLE_CHARS).collect();
+    if line.chars().count() > FALLBACK_TITLE_CHARS {
+        title = format!("{}…", title.trim_end());
+    }
+    title
+}
+
+// Only the start of a long prompt or reply is needed to name the conversation
+const TITLE_EXCHANGE_CHARS: usize = 2_000;
+
+/// Payload of the `session-titled` event, emitted when a session gets a generated title.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SessionTitledPayload {
+    pub session_id: i64,
+    pub title: String,
+}
+
+// Title a session from its first prompt and reply, using `title_model` when configured and
+// prefixing the model name when `title_model_prefix` is on; returns the new title
+pub async fn title_session(
+    session_id: i64,
+    model: &str,
+    db_conn: &db::DbPool,
+    limiter: &Arc<Mutex<RequestLimiter>>,
+) -> Result<String, String> {
+    let keep_alive = resolve_keep_alive(None, db_conn).await?;
+    let (title_model, prefix, (prompt, reply)) = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        let title_model = db::get_config_value(&conn, "title_model").ok().flatten().filter(|m| !m.is_empty());
+        let prefix = db::get_config_value(&conn, settings::TITLE_MODEL_PREFIX_KEY).ok().flatten().as_deref() == Some("true");
+        let exchange = db::fetch_first_exchange(&conn, session_id).map_err(|e| e.to_string())?;
+        (title_model.unwrap_or_else(|| model.to_string()), prefix, exchange)
+    };
+    let Some(prompt) = prompt else {
+        return Err(format!("Session {} has no messages to title", session_id));
+    };
+
+    let excerpt = |text: &str| text.chars().take(TITLE_EXCHANGE_CHARS).collect::<String>();
+    let mut exchange = format!("User: {}", excerpt(&prompt));
+    if let Some(reply) = reply.map(|r| strip_think(&r)).filter(|r| !r.trim().is_empty()) {
+        exchange.push_str(&format!("\n\nAssistant: {}", excerpt(&reply)));
+    }
+
+    let title = {
+        let _permit = session::acquire_permit(limiter).await;
+        generate_session_title_with_ai(&exchange, &title_model, keep_alive.as_ref()).await?
+    };
+    let title = if prefix { format!("{}: {}", title_model, title) } else { title };
+
+    let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+    db::set_session_title(&conn, session_id, &title).map_err(|e| e.to_string())?;
+    Ok(title)
+}
+
+/// Rough token count of a text, at about four characters per token.
+pub fn approx_token_count(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+// Rough token count of a chat message: its text plus some framing
+fn estimate_tokens(message: &serde_json::Value) -> usize {
+    message["content"].as_str().map_or(0, approx_token_count) + 4
+}
+
+/// Keeps the system messages and the most recent messages that fit within `max_tokens`,
+/// returning them with the number of older messages dropped. The newest message is always
+/// kept, and the kept history never starts with an assistant reply whose prompt was dropped.
+pub fn build_context(messages: Vec<serde_json::Value>, max_tokens: usize) -> (Vec<serde_json::Value>, usize) {
+    let (system, history): (Vec<_>, Vec<_>) = messages.into_iter().partition(|m| m["role"] == "system");
+
+    let mut budget = max_tokens.saturating_sub(system.iter().map(estimate_tokens).sum());
+    let mut keep = 0;
+    for message in history.iter().rev() {
+        let cost = estimate_tokens(message);
+        if keep > 0 && cost > budget {
+            break;
+        }
+        budget = budget.saturating_sub(cost);
+        keep += 1;
+    }
+    let mut start = history.len() - keep;
+    while start + 1 < history.len() && history[start]["role"] == "assistant" {
+        start += 1;
+    }
+
+    let dropped = start;
+    let context = system.into_iter().chain(history.into_iter().skip(start)).collect();
+    (context, dropped)
+}
+
+/// Per-call inputs of a chat generation.
+#[derive(Debug, Default)]
+pub struct ChatRequest {
+    pub prompt: String,
+    pub model: String,
+    /// Overrides the configured keep_alive policy for this request.
+    pub keep_alive: Option<String>,
+    /// Send only the current prompt to the model, ignoring earlier turns.
+    /// The exchange is still saved into the session.
+    pub no_history: bool,
+    /// Sampling options for this request; they are also saved as the session's options.
+    pub options: Option<GenerationOptions>,
+    /// Base64-encoded images attached to the prompt, for vision models.
+    pub images: Vec<String>,
+}
+
+/// Payload of the `chat-token` and `chat-thinking` events, emitted for every streamed
+/// fragment of the answer and of the reasoning respectively.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChunkPayload {
+    pub session_id: i64,
+    pub message_id: i64,
+    pub content: String,
+}
+
+/// Payload of the `chat-rate` event, emitted periodically while a reply streams.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChatRatePayload {
+    pub session_id: i64,
+    pub message_id: i64,
+    pub tokens: u64,
+    pub tokens_per_second: f64,
+}
+
+// Minimum time between two `chat-rate` events
+const RATE_EMIT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Payload of the `response-truncated` event.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TruncatedPayload {
+    pub session_id: i64,
+    pub message_id: i64,
+}
+
+/// Payload of the `chat-done` event, emitted once the assistant message has been finalized.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChatDonePayload {
+    pub session_id: i64,
+    /// Id of the assistant row; the row is removed when nothing was generated.
+    pub message_id: i64,
+    pub content: String,
+    /// Reasoning streamed before the answer, when thinking output is shown.
+    pub thinking: Option<String>,
+    /// The generation was aborted and `content` is partial.
+    pub stopped: bool,
+    /// The reply hit the `num_predict` length cap.
+    pub truncated: bool,
+    /// Set when a JSON `format` was requested but the completed reply doesn't parse as JSON.
+    pub format_warning: Option<String>,
+    /// Token counts, timings and tokens per second from the final chunk.
+    pub stats: Option<GenerationStats>,
+    pub error: Option<String>,
+}
+
+/// Ids of the rows created by `start_chat_generation` before streaming begins.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StartedGeneration {
+    pub session_id: i64,
+    pub user_message_id: i64,
+    pub message_id: i64,
+}
+
+/// A generation whose session, user message and placeholder assistant row already exist.
+pub struct PreparedGeneration {
+    pub started: StartedGeneration,
+    model: String,
+    request_body: serde_json::Value,
+    show_thinking: bool,
+    /// A `format` was requested, so the reply should be JSON.
+    expects_json: bool,
+    /// The session was created for this prompt and still has the placeholder title.
+    needs_title: bool,
+    run: GenerationRun,
+    // Held until streaming finishes
+    _permit: OwnedSemaphorePermit,
+}
+
+// Resolve the session, save the user prompt and insert an empty assistant row to stream into
+pub async fn prepare_chat_generation(
+    mut request: ChatRequest,
+    window: &Window,
+    state: &State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: &State<'_, db::DbPool>,
+    limiter: &State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<PreparedGeneration, GenerationError> {
+    // Checked before the generation is marked running, so a failure here leaves the state alone
+    request.model = resolve_chat_model(request.model, state.inner(), db_conn.inner()).await?;
+    ensure_model_available(&request.model, window.state::<Arc<Mutex<ModelCache>>>().inner()).await?;
+    request.images = request.images.iter().map(|image| normalize_image(image)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(format) = request.options.as_ref().and_then(|o| o.format.as_ref()) {
+        check_format(format)?;
+    }
+    // An empty stop sequence would end every reply before it starts
+    if request.options.as_ref().and_then(|o| o.stop.as_ref()).is_some_and(|stop| stop.iter().any(|s| s.is_empty())) {
+        return Err("Stop sequences cannot be empty".to_string().into());
+    }
+    let keep_alive = resolve_keep_alive(request.keep_alive.take(), db_conn.inner()).await?;
+
+    let run = state.lock().await.begin(None).ok_or(GenerationError::AlreadyRunning)?;
+    let result = try_prepare_chat_generation(request, keep_alive, &run, window, state, db_conn, limiter).await;
+    // Failing before streaming starts must not leave the generation marked as running
+    if result.is_err() {
+        state.lock().await.finish(&run);
+    }
+    result
+}
+
+// An empty `model` means the model of the current session
+async fn resolve_chat_model(model: String, state: &Arc<Mutex<GenerationState>>, db_conn: &db::DbPool) -> Result<String, String> {
+    let model = model.trim();
+    if !model.is_empty() {
+        return Ok(model.to_string());
+    }
+    let Some(session_id) = state.lock().await.current_session_id.filter(|id| *id != -1) else {
+        return Err("No model selected".to_string());
+    };
+    let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+    db::get_session_config(&conn, session_id)
+        .map_err(|e| e.to_string())?
+        .model
+        .filter(|m| !m.trim().is_empty())
+        .ok_or_else(|| "No model selected for this chat".to_string())
+}
+
+async fn try_prepare_chat_generation(
+    request: ChatRequest,
+    keep_alive: Option<serde_json::Value>,
+    run: &GenerationRun,
+    window: &Window,
+    state: &State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: &State<'_, db::DbPool>,
+    limiter: &State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<PreparedGeneration, GenerationError> {
+    let ChatRequest { prompt, model, no_history, options, images, .. } = request;
+    let default_options = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        db::get_default_options(&conn)
+    };
+
+    // Held until streaming finishes
+    let permit = session::acquire_permit(limiter).await;
+
+    let session_id;
+    let needs_title;
+
+    {
+        let mut generation_state = state.lock().await;
+        // Stopped while waiting for the permit
+        if run.token.is_cancelled() {
+            return Err("Generation was cancelled".to_string().into());
+        }
+        generation_state.model = Some(model.clone());
+
+        // New sessions start with a title cut from the prompt and are titled by the model once the
+        // first reply is in, so the title covers the whole exchange and a failure never blocks the chat
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        match generation_state.current_session_id.filter(|id| *id != -1) {
+            None => {
+                needs_title = true;
+                let new_session_id = db::create_session(&conn, &fallback_title(&prompt))
+                    .map_err(|e| format!("Failed to create session: {}", e))?;
+
+                generation_state.current_session_id = Some(new_session_id);
+                session_id = new_session_id;
+                if let Err(e) = db::set_last_session(&conn, Some(new_session_id)) {
+                    eprintln!("Failed to remember the last session: {}", e);
+                }
+            }
+            // A session made by `new_chat` is titled like a new one unless it was renamed before the first message
+            Some(current_id) => {
+                session_id = current_id;
+                needs_title = db::count_session_messages(&conn, session_id).map_err(|e| e.to_string())? == 0
+                    && db::get_session_title(&conn, session_id).map_err(|e| e.to_string())?.as_deref() == Some(NEW_CHAT_TITLE);
+                if needs_title {
+                    db::set_session_title(&conn, session_id, &fallback_title(&prompt))
+                        .map_err(|e| format!("Failed to title session: {}", e))?;
+                }
+            }
+        }
+        generation_state.generating_session_id = Some(session_id);
+    }
+
+    // Sessions remember the model they were started with so later prompts can leave it out
+    if let Err(e) = db::checkout(db_conn).and_then(|conn| db::init_session_model(&conn, session_id, &model)) {
+        eprintln!("Failed to save session model: {}", e);
+    }
+
+    // Warn when the session was pinned to a digest that the local tag no longer points to
+    match check_session_model_digest(session_id, db_conn.inner()).await {
+        Ok(Some(warning)) if warning.model == model => {
+            if let Err(e) = window.emit("model-changed", warning) {
+                eprintln!("Failed to emit model-changed event: {}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to check pinned model digest: {}", e),
+    }
+
+    // save user prompt in chat history
+    let user_message_id = db::save_chat_message(session_id, "user", &prompt, None, db_conn.clone())
+        .await
+        .map_err(|e| format!("Failed to save user message: {}", e))?;
+    let attachments = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        if !images.is_empty() {
+            db::save_message_images(&conn, user_message_id, &images)
+                .map_err(|e| format!("Failed to save images: {}", e))?;
+        }
+        db::claim_pending_attachments(&conn, session_id, user_message_id)
+            .map_err(|e| format!("Failed to load attachments: {}", e))?
+    };
+
+    let (session_config, show_thinking) = {
+        let conn = db::checkout(db_conn).map_err(|e| e.to_string())?;
+        // Remember the last options used so reopening the chat restores them
+        if options.is_some() {
+            if let Err(e) = db::set_session_options(&conn, session_id, &options) {
+                eprintln!("Failed to save session options: {}", e);
+            }
+        }
+        (
+            db::get_session_config(&conn, session_id).unwrap_or_default(),
+            db::resolve_show_thinking(&conn, session_id).unwrap_or(true),
+        )
+    };
+
+    let mut messages = if no_history {
+        let mut message = serde_json::json!({ "role": "user", "content": prompt });
+        if !images.is_empty() {
+            message["images"] = serde_json::json!(images);
+        }
+        if !attachments.is_empty() {
+            message["attachments"] = serde_json::json!(attachments);
+        }
+        vec![message]
+    } else {
+        load_chat_history(state.clone(), db_conn.clone())
+            .await
+            .unwrap_or_else(|_| Vec::new())
+    };
+    messages.iter_mut().for_each(inline_attachments);
+
+    // The system prompt lives on the session, not in chat_history, so it never shows in the transcript
+    if let Some(system_prompt) = session_config.system_prompt.filter(|p| !p.trim().is_empty()) {
+        messages.insert(0, serde_json::json!({ "role": "system", "content": system_prompt }));
+    }
 
-// Generate a chat session title
-pub async fn generate_session_title_with_ai(prompt: &str, model: &str) -> Result<String, String> {
-    let client = Client::new();
+    if let Some(max_tokens) = session_config.context_tokens.filter(|t| *t > 0) {
+        let (trimmed, dropped) = build_context(messages, max_tokens as usize);
+        if dropped > 0 {
+            println!("Dropped {} older messages of session {} to fit {} tokens", dropped, session_id, max_tokens);
+        }
+        messages = trimmed;
+    }
 
-    let request_body = serde_json::json!({
+    let mut request_body = serde_json::json!({
         "model": model,
-        "prompt": format!(
-            "Generate a concise and informative title (at most 10 words) summarizing the prompt. 
-            Respond with only the title as plain text. Do not include any explanations, formatting, 
-            or additional content. The prompt to summarize is: ```{}```",
-            prompt
-        )
+        "messages": messages
     });
+    if let Some(options) = session_config.options.as_ref().or(default_options.as_ref()) {
+        apply_options(&mut request_body, options);
+    }
+    if let Some(keep_alive) = &keep_alive {
+        request_body["keep_alive"] = keep_alive.clone();
+    }
 
-    let response = client
-        .post("http://localhost:11434/api/generate")
-        .header("Content-Type", "application/json")
-        .json(&request_body)
-        .send()
+    // Insert the assistant row up front so its id is stable for the whole stream
+    let message_id = db::save_chat_message(session_id, "assistant", "", Some(&model), db_conn.clone())
         .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+        .map_err(|e| format!("Failed to save assistant message: {}", e))?;
 
-    let mut full_response = String::new();
+    Ok(PreparedGeneration {
+        started: StartedGeneration {
+            session_id,
+            user_message_id,
+            message_id,
+        },
+        model,
+        expects_json: request_body.get("format").is_some(),
+        request_body,
+        show_thinking,
+        needs_title,
+        run: run.clone(),
+        _permit: permit,
+    })
+}
 
-    let mut stream = response.bytes_stream();
+/// Separates streamed content into reasoning inside `<think>...</think>` and the answer.
+/// Text that could be the start of a tag split across chunks is held back until the next chunk.
+#[derive(Debug, Default)]
+struct ThinkSplitter {
+    in_think: bool,
+    pending: String,
+}
 
-    while let Some(chunk_result) = stream.next().await {
-        match chunk_result {
-            Ok(bytes) => {
-                let text = String::from_utf8_lossy(&bytes);
-                for line in text.lines() {
-                    if let Ok(parsed) = serde_json::from_str::<AIResponse>(line) {
-                        full_response.push_str(&parsed.response);
-                        if parsed.done {
-                            break;
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                return Err(format!("Error reading stream: {}", e));
+impl ThinkSplitter {
+    /// Consumes a fragment and returns the answer and reasoning text it completes.
+    fn push(&mut self, text: &str) -> (String, String) {
+        self.pending.push_str(text);
+        let mut answer = String::new();
+        let mut reasoning = String::new();
+        loop {
+            let (tag, out) = if self.in_think {
+                ("</think>", &mut reasoning)
+            } else {
+                ("<think>", &mut answer)
+            };
+            if let Some(pos) = self.pending.find(tag) {
+                out.push_str(&self.pending[..pos]);
+                self.pending.drain(..pos + tag.len());
+                self.in_think = !self.in_think;
+            } else {
+                // Keep the longest suffix that could begin the tag
+                let keep = (1..tag.len()).rev().find(|&n| self.pending.ends_with(&tag[..n])).unwrap_or(0);
+                let split = self.pending.len() - keep;
+                out.extend(self.pending.drain(..split));
+                break;
             }
         }
+        (answer, reasoning)
     }
 
-    let re_think = Regex::new(r"(?s)^<think>.*?</think>(\s*)").unwrap();
-    let title = re_think.replace(&full_response, "").trim_matches('"').trim_matches('*').to_string();
-
-    let final_title = format!("{}: {}", model, title);
+    /// Returns the held-back text once the stream has ended.
+    fn finish(&mut self) -> (String, String) {
+        let rest = std::mem::take(&mut self.pending);
+        if self.in_think {
+            (String::new(), rest)
+        } else {
+            (rest, String::new())
+        }
+    }
+}
 
-    Ok(final_title)
+/// One JSON line of a streamed `/api/chat` reply.
+#[derive(Debug)]
+struct StreamLine {
+    /// Reasoning sent in the separate `thinking` field.
+    thinking: Option<String>,
+    /// Answer text, which may still contain inline `<think>` tags.
+    content: Option<String>,
+    /// Present on the final line.
+    done: Option<StreamDone>,
 }
 
-pub async fn process_chat_generation(
-    prompt: String,
-    model: String,
-    state: State<'_, Arc<Mutex<GenerationState>>>,
-    db_conn: State<'_, Arc<Mutex<rusqlite::Connection>>>,
-) -> Result<String, String> {
-    let cancellation_token;
+#[derive(Debug)]
+struct StreamDone {
+    /// The reply was cut off by the token limit.
+    truncated: bool,
+    stats: Option<GenerationStats>,
+}
 
-    {
-        let mut generation_state = state.lock().await;
-        generation_state.is_running = true;
-        generation_state.cancellation_token = Some(CancellationToken::new());
-        cancellation_token = generation_state.cancellation_token.clone().unwrap();
+// Parse one line of a streamed chat reply. Lines that aren't JSON yield `None`, and an `error`
+// line becomes `StreamError` so the caller stops streaming and keeps the partial reply
+fn parse_stream_line(line: &str) -> Result<Option<StreamLine>, GenerationError> {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(line.trim()) else {
+        return Ok(None);
+    };
+    if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
+        return Err(GenerationError::StreamError(error.to_string()));
+    }
 
-        if generation_state.current_session_id.is_none() || generation_state.current_session_id == Some(-1) {
-            let generated_title = generate_session_title_with_ai(&prompt, &model)
-                .await
-                .map_err(|e| format!("Failed to generate session title: {}", e))?;
+    let message_field = |field: &str| json.get("message").and_then(|m| m.get(field)).and_then(|c| c.as_str()).map(str::to_string);
+    let mut parsed = StreamLine {
+        thinking: message_field("thinking"),
+        content: message_field("content"),
+        done: None,
+    };
+    if json.get("done").and_then(|d| d.as_bool()).unwrap_or(false) {
+        parsed.done = Some(StreamDone {
+            truncated: json.get("done_reason").and_then(|r| r.as_str()) == Some("length"),
+            stats: serde_json::from_value::<GenerationStats>(json).ok().map(GenerationStats::with_rate),
+        });
+    }
+    Ok(Some(parsed))
+}
 
-            let new_session_id = db::get_or_create_session(db_conn.inner(), &generated_title)
-                .await
-                .map_err(|e| format!("Failed to create or retrieve session: {}", e))?;
+// Append split stream text to the reply and its reasoning, emitting `chat-token` and `chat-thinking` events
+fn append_split(
+    window: &Window,
+    session_id: i64,
+    message_id: i64,
+    (answer, reasoning): (String, String),
+    ai_response: &mut String,
+    thinking: &mut String,
+) {
+    if !reasoning.is_empty() {
+        thinking.push_str(&reasoning);
+        let _ = window.emit("chat-thinking", ChunkPayload { session_id, message_id, content: reasoning });
+    }
+    // Models separate the answer from the reasoning with blank lines
+    let answer = if ai_response.is_empty() { answer.trim_start().to_string() } else { answer };
+    if !answer.is_empty() {
+        ai_response.push_str(&answer);
+        let _ = window.emit("chat-token", ChunkPayload { session_id, message_id, content: answer });
+    }
+}
 
-            generation_state.current_session_id = Some(new_session_id);
+// Finalize the assistant row of a finished reply, stopped and truncated ones included,
+// or drop the row if nothing was generated
+fn save_reply(conn: &rusqlite::Connection, done: &ChatDonePayload) -> rusqlite::Result<()> {
+    if done.content.is_empty() && done.thinking.is_none() {
+        return db::delete_chat_message(conn, done.message_id);
+    }
+    db::finalize_message(conn, done.message_id, &done.content, done.thinking.as_deref(), done.stopped, done.truncated)?;
+    if let Some(stats) = &done.stats {
+        if let Err(e) = db::save_message_stats(conn, done.message_id, stats) {
+            eprintln!("Failed to save message stats: {}", e);
         }
     }
+    Ok(())
+}
 
-    let session_id = {
-        let state_guard = state.lock().await;
-        state_guard.current_session_id.unwrap_or(-1)
-    };
-
-    // save user prompt in chat history
-    db::save_chat_message(session_id, "user", &prompt, db_conn.clone())
-        .await
-        .map_err(|e| format!("Failed to save user message: {}", e))?;
-
-    let messages = load_chat_history(state.clone(), db_conn.clone())
-        .await
-        .unwrap_or_else(|_| Vec::new());
+// Stream the model's reply into the prepared assistant row, emitting `chat-token` events as it arrives
+pub async fn stream_chat_generation(
+    prepared: PreparedGeneration,
+    window: Window,
+    state: Arc<Mutex<GenerationState>>,
+    db_conn: db::DbPool,
+) -> Result<String, GenerationError> {
+    let StartedGeneration { session_id, message_id, .. } = prepared.started;
+    let cancellation_token = prepared.run.token.clone();
 
     let mut ai_response = String::new();
+    let mut thinking = String::new();
+    let mut splitter = ThinkSplitter::default();
+    let mut stats: Option<GenerationStats> = None;
+    let mut stopped = false;
+    let mut truncated = false;
 
-    let generation_result: Result<(), String> = tokio::select! {
+    let generation_result: Result<(), GenerationError> = tokio::select! {
         result = async {
-            let client = reqwest::Client::new();
-
-            let response = client
-                .post("http://localhost:11434/api/chat")
-                .json(&serde_json::json!({
-                    "model": model,
-                    "messages": messages
-                }))
-                .send()
+            let client = generation_client();
+
+            // Only the request is retried; once tokens arrive a retry would duplicate the reply
+            let response = retry_with_backoff(|| client.post(format!("{}/chat", api_url())).json(&prepared.request_body))
                 .await
-                .map_err(|e| format!("Failed to make API call: {}", e))?;
+                .map_err(OllamaError::from)?;
 
             if !response.status().is_success() {
-                return Err(format!("API call failed with status: {}", response.status()));
+                let message = response_error(response).await;
+                if is_template_error(&message) {
+                    return Err(GenerationError::TemplateError(message));
+                }
+                return Err(message.into());
             }
 
             let mut stream = response.bytes_stream();
-            loop {
+            // Network chunks don't align with Ollama's JSON lines, so buffer until a newline
+            let mut buffer = String::new();
+            // Ollama streams one token per line, which is close enough for a live meter
+            let started_at = std::time::Instant::now();
+            let mut last_rate_emit = started_at;
+            let mut tokens: u64 = 0;
+            'stream: loop {
                 tokio::select! {
                     chunk = stream.next() => {
-                        if let Some(chunk) = chunk {
-                            let data = chunk.map_err(|e| e.to_string())?;
-                            let text_chunk = String::from_utf8_lossy(&data);
-
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text_chunk) {
-                                if let Some(text) = json.get("message").and_then(|m| m.get("content")).and_then(|c| c.as_str()) {
-                                    ai_response.push_str(text);
-                                }
-
-                                if json.get("done").and_then(|d| d.as_bool()).unwrap_or(false) {
-                                    break;
-                                }
-                            }
-                        } else {
+                        let Some(chunk) = chunk else {
                             break;
+                        };
+                        let data = chunk.map_err(|e| e.to_string())?;
+                        buffer.push_str(&String::from_utf8_lossy(&data));
+
+                        while let Some(newline) = buffer.find('\n') {
+                            let line: String = buffer.drain(..=newline).collect();
+                            // Keep what was produced so far and stop on a mid-stream error
+                            let Some(parsed) = parse_stream_line(&line)? else {
+                                continue;
+                            };
+
+                            if let Some(text) = parsed.thinking {
+                                tokens += 1;
+                                append_split(&window, session_id, message_id, (String::new(), text), &mut ai_response, &mut thinking);
+                            }
+                            if let Some(text) = parsed.content {
+                                tokens += 1;
+                                let split = splitter.push(&text);
+                                append_split(&window, session_id, message_id, split, &mut ai_response, &mut thinking);
+                            }
+
+                            if last_rate_emit.elapsed() >= RATE_EMIT_INTERVAL {
+                                last_rate_emit = std::time::Instant::now();
+                                let elapsed = started_at.elapsed().as_secs_f64();
+                                let _ = window.emit("chat-rate", ChatRatePayload {
+                                    session_id,
+                                    message_id,
+                                    tokens,
+                                    tokens_per_second: if elapsed > 0.0 { tokens as f64 / elapsed } else { 0.0 },
+                                });
+                            }
+
+                            if let Some(done) = parsed.done {
+                                truncated = done.truncated;
+                                stats = done.stats;
+                                break 'stream;
+                            }
                         }
                     },
                     _ = cancellation_token.cancelled() => {
-                        ai_response.push_str("\n\nCancelled\n");
+                        stopped = true;
                         println!("Generation task was cancelled");
                         break;
                     }
@@ -186,27 +1804,660 @@ pub async fn process_chat_generation(
             result
         },
         _ = cancellation_token.cancelled() => {
+            stopped = true;
             println!("Cancellation token triggered");
             Ok(())
         }
     };
 
-    // Save assistant response in chat history
-    db::save_chat_message(session_id, "assistant", &ai_response, db_conn.clone())
-        .await
-        .map_err(|e| format!("Failed to save assistant message: {}", e))?;
+    // Keep the sampling settings with the reply so it can be reproduced, even when Ollama sent no final stats
+    let request_options = &prepared.request_body["options"];
+    let (seed, temperature, num_ctx) =
+        (request_options["seed"].as_i64(), request_options["temperature"].as_f64(), request_options["num_ctx"].as_i64());
+    if seed.is_some() || temperature.is_some() || num_ctx.is_some() {
+        let stats = stats.get_or_insert_with(GenerationStats::default);
+        stats.seed = seed;
+        stats.temperature = temperature;
+        stats.num_ctx = num_ctx;
+    }
 
-    let mut generation_state = state.lock().await;
-    generation_state.is_running = false;
-    generation_state.cancellation_token = None;
+    // Text held back as a possible partial tag belongs to whichever part was streaming last
+    append_split(&window, session_id, message_id, splitter.finish(), &mut ai_response, &mut thinking);
+    // The reasoning is only kept for display, so drop it when the user hides it
+    let thinking = Some(thinking).filter(|t| prepared.show_thinking && !t.trim().is_empty());
+
+    // A stopped reply is partial, so it is not expected to parse
+    let format_warning = (prepared.expects_json && !stopped)
+        .then(|| serde_json::from_str::<serde_json::Value>(&ai_response).err())
+        .flatten()
+        .map(|e| format!("The reply is not valid JSON: {}", e));
+    let mut done = ChatDonePayload {
+        session_id,
+        message_id,
+        content: ai_response,
+        thinking,
+        stopped,
+        truncated,
+        format_warning,
+        stats,
+        error: None,
+    };
+    let saved = db::checkout(&db_conn).and_then(|conn| save_reply(&conn, &done));
+
+    // Always reset the running state, even if saving failed
+    state.lock().await.finish(&prepared.run);
+
+    let generation_result = generation_result.and_then(|_| {
+        saved.map_err(|e| GenerationError::from(format!("Failed to save assistant message: {}", e)))
+    });
+
+    // Keep the session's search embedding current once the conversation settles
+    if let Some(scheduler) = window.try_state::<Arc<Mutex<EmbeddingScheduler>>>() {
+        embeddings::schedule_session_embedding(scheduler.inner().clone(), db_conn.clone(), session_id);
+    }
+
+    if truncated {
+        let _ = window.emit("response-truncated", TruncatedPayload { session_id, message_id });
+    }
+
+    done.error = generation_result.as_ref().err().map(|e| e.to_string());
+    let _ = window.emit("chat-done", &done);
+    let ai_response = done.content;
+
+    if prepared.needs_title && generation_result.is_ok() && !ai_response.is_empty() {
+        let (window, db_conn, model) = (window.clone(), db_conn.clone(), prepared.model.clone());
+        tauri::async_runtime::spawn(async move {
+            let limiter = window.state::<Arc<Mutex<RequestLimiter>>>();
+            match title_session(session_id, &model, &db_conn, limiter.inner()).await {
+                Ok(title) => {
+                    let _ = window.emit("session-titled", SessionTitledPayload { session_id, title });
+                }
+                // The session keeps its prompt-based title
+                Err(e) => eprintln!("Failed to generate title for session {}, keeping the fallback: {}", session_id, e),
+            }
+        });
+    }
 
     match generation_result {
         Ok(_) => Ok(ai_response),
         Err(e) => {
-            println!("Error generating chat: {}", e);
+            println!("Error generating chat with {}: {}", prepared.model, e);
             Err(e)
         }
     }
 }
 
+pub async fn process_chat_generation(
+    request: ChatRequest,
+    window: Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<String, GenerationError> {
+    let prepared = prepare_chat_generation(request, &window, &state, &db_conn, &limiter).await?;
+    stream_chat_generation(prepared, window, state.inner().clone(), db_conn.inner().clone()).await
+}
+
+/// Result of `quick_chat`.
+#[derive(Debug, serde::Serialize)]
+pub struct QuickChatResult {
+    pub session_id: i64,
+    pub response: String,
+}
+
+// Create a session with the given title (skipping AI titling), make it current and generate in it
+pub async fn quick_chat(
+    title: String,
+    request: ChatRequest,
+    window: Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<QuickChatResult, GenerationError> {
+    let session_id = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        let session_id = db::create_session(&conn, title.trim())
+            .map_err(|e| format!("Failed to create session: {}", e))?;
+        if let Err(e) = db::set_last_session(&conn, Some(session_id)) {
+            eprintln!("Failed to remember the last session: {}", e);
+        }
+        session_id
+    };
+    state.lock().await.current_session_id = Some(session_id);
+
+    let response = process_chat_generation(request, window, state, db_conn, limiter).await?;
+
+    Ok(QuickChatResult { session_id, response })
+}
+
+// Create the user and assistant rows, return their ids right away and stream the reply in the background
+pub async fn start_chat_generation(
+    request: ChatRequest,
+    window: Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<StartedGeneration, GenerationError> {
+    let prepared = prepare_chat_generation(request, &window, &state, &db_conn, &limiter).await?;
+    let started = prepared.started.clone();
+
+    let state = state.inner().clone();
+    let db_conn = db_conn.inner().clone();
+    tauri::async_runtime::spawn(async move {
+        // Failures are reported to the UI through the `chat-done` event
+        let _ = stream_chat_generation(prepared, window, state, db_conn).await;
+    });
+
+    Ok(started)
+}
+
+// Send a non-streaming /api/chat request and return the reply content
+async fn chat_once(client: &Client, request_body: &serde_json::Value) -> Result<String, String> {
+    let response = client
+        .post(format!("{}/chat", api_url()))
+        .json(request_body)
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(response_error(response).await);
+    }
+
+    let data: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+    Ok(data["message"]["content"].as_str().unwrap_or_default().to_string())
+}
+
+// Sample `n` responses to a prompt concurrently, each with its own seed, without saving anything
+pub async fn generate_candidates(
+    prompt: String,
+    model: String,
+    n: usize,
+    options: Option<GenerationOptions>,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<Vec<Candidate>, String> {
+    let n = n.clamp(1, MAX_CANDIDATES);
+    let options = options.unwrap_or_default();
+    let keep_alive = resolve_keep_alive(None, db_conn.inner()).await?;
+
+    let mut messages = load_chat_history(state.clone(), db_conn.clone())
+        .await
+        .unwrap_or_else(|_| Vec::new());
+    messages.push(serde_json::json!({ "role": "user", "content": prompt }));
+
+    // Vary the seed per candidate, starting from the requested one if given
+    let base_seed = options.seed.unwrap_or_else(|| chrono::Utc::now().timestamp_subsec_nanos() as i64);
+
+    let run = {
+        let mut generation_state = state.lock().await;
+        let session_id = generation_state.current_session_id;
+        generation_state.begin(session_id).ok_or_else(|| session::ALREADY_GENERATING.to_string())?
+    };
+    let cancellation_token = run.token.clone();
+
+    let client = generation_client();
+    let requests = (0..n).map(|i| {
+        let seed = base_seed + i as i64;
+        let mut request_body = serde_json::json!({
+            "model": model,
+            "messages": messages,
+            "stream": false
+        });
+        apply_options(&mut request_body, &GenerationOptions { seed: Some(seed), ..options.clone() });
+        if let Some(keep_alive) = &keep_alive {
+            request_body["keep_alive"] = keep_alive.clone();
+        }
+        let client = &client;
+        let limiter = limiter.inner().clone();
+
+        async move {
+            let _permit = session::acquire_permit(&limiter).await;
+            match chat_once(client, &request_body).await {
+                Ok(content) => Candidate { seed, content, error: None },
+                Err(e) => Candidate { seed, content: String::new(), error: Some(e) },
+            }
+        }
+    });
+
+    let result = tokio::select! {
+        candidates = futures_util::future::join_all(requests) => Ok(candidates),
+        _ = cancellation_token.cancelled() => Err("Candidate generation was cancelled".to_string()),
+    };
+
+    state.lock().await.finish(&run);
+
+    result
+}
+
+/// A user turn of a replayed session with the original and the new reply.
+#[derive(Debug, serde::Serialize)]
+pub struct ReplayTurn {
+    pub prompt: String,
+    pub original: Option<String>,
+    pub replayed: String,
+    /// Word-level divergence from the original reply, 0.0 (identical) to 1.0.
+    pub divergence: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Result of `replay_session`.
+#[derive(Debug, serde::Serialize)]
+pub struct ReplayResult {
+    pub session_id: i64,
+    pub model: String,
+    pub cumulative: bool,
+    pub turns: Vec<ReplayTurn>,
+    /// Mean divergence over the turns that have an original reply.
+    pub mean_divergence: Option<f64>,
+}
+
+// Re-run every user turn of a session against `model` without modifying the session.
+// With `cumulative`, each turn sees the earlier prompts and replayed replies; otherwise it starts fresh.
+pub async fn replay_session(
+    session_id: i64,
+    model: String,
+    cumulative: bool,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<ReplayResult, String> {
+    let keep_alive = resolve_keep_alive(None, db_conn.inner()).await?;
+    let session_config = {
+        let conn = db::checkout(&db_conn).map_err(|e| e.to_string())?;
+        db::get_session_config(&conn, session_id).map_err(|e| e.to_string())?
+    };
+    let history = db::fetch_chat_history(session_id, db_conn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let system_message = session_config
+        .system_prompt
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| serde_json::json!({ "role": "system", "content": p }));
+    // Earlier prompts and replayed replies, used only in cumulative mode
+    let mut context: Vec<serde_json::Value> = Vec::new();
+
+    let run = state
+        .lock()
+        .await
+        .begin(Some(session_id))
+        .ok_or_else(|| session::ALREADY_GENERATING.to_string())?;
+    let cancellation_token = run.token.clone();
+    let _permit = session::acquire_permit(&limiter).await;
+    let client = generation_client();
+    let mut turns = Vec::new();
+
+    for turn in db::group_turns(history) {
+        let Some(user) = turn.user else {
+            continue;
+        };
+        if cancellation_token.is_cancelled() {
+            break;
+        }
+
+        let mut messages: Vec<serde_json::Value> = system_message.iter().chain(&context).cloned().collect();
+        messages.push(serde_json::json!({ "role": "user", "content": user.message }));
+
+        let mut request_body = serde_json::json!({
+            "model": model,
+            "messages": messages,
+            "stream": false
+        });
+        if let Some(options) = &session_config.options {
+            apply_options(&mut request_body, options);
+        }
+        if let Some(keep_alive) = &keep_alive {
+            request_body["keep_alive"] = keep_alive.clone();
+        }
+
+        let result = tokio::select! {
+            result = chat_once(&client, &request_body) => result,
+            _ = cancellation_token.cancelled() => break,
+        };
+        let original = turn.assistant.map(|m| strip_think(&m.message));
+        let (replayed, error) = match result {
+            Ok(content) => (strip_think(&content), None),
+            Err(e) => (String::new(), Some(e)),
+        };
+
+        if cumulative {
+            context.push(serde_json::json!({ "role": "user", "content": user.message }));
+            context.push(serde_json::json!({ "role": "assistant", "content": replayed }));
+        }
+
+        turns.push(ReplayTurn {
+            prompt: user.message,
+            divergence: match (&original, &error) {
+                (Some(original), None) => Some(diff::word_divergence(original, &replayed)),
+                _ => None,
+            },
+            original,
+            replayed,
+            error,
+        });
+    }
+
+    state.lock().await.finish(&run);
+
+    let scores: Vec<f64> = turns.iter().filter_map(|t| t.divergence).collect();
+    let mean_divergence = if scores.is_empty() {
+        None
+    } else {
+        Some(scores.iter().sum::<f64>() / scores.len() as f64)
+    };
+
+    Ok(ReplayResult {
+        session_id,
+        model,
+        cumulative,
+        turns,
+        mean_divergence,
+    })
+}
+
+// Stream a /api/chat request, invoking `on_content` for every content fragment.
+// Lines are buffered so JSON objects split across network chunks are still parsed.
+async fn stream_chat<F: FnMut(&str)>(
+    client: &Client,
+    request_body: &serde_json::Value,
+    mut on_content: F,
+) -> Result<String, String> {
+    let response = client
+        .post(format!("{}/chat", api_url()))
+        .json(request_body)
+        .send()
+        .await
+        .map_err(OllamaError::from)?;
+
+    if !response.status().is_success() {
+        return Err(response_error(response).await);
+    }
+
+    let mut content = String::new();
+    let mut buffer = String::new();
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let data = chunk.map_err(|e| format!("Error reading stream: {}", e))?;
+        buffer.push_str(&String::from_utf8_lossy(&data));
+
+        while let Some(newline) = buffer.find('\n') {
+            let line: String = buffer.drain(..=newline).collect();
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(line.trim()) else {
+                continue;
+            };
+
+            if let Some(error) = json["error"].as_str() {
+                return Err(format!("Ollama error during generation: {}", error));
+            }
+            if let Some(text) = json["message"]["content"].as_str() {
+                content.push_str(text);
+                on_content(text);
+            }
+            if json["done"].as_bool().unwrap_or(false) {
+                return Ok(content);
+            }
+        }
+    }
+
+    Ok(content)
+}
+
+/// Sessions longer than this many characters are summarized in chunks.
+const SUMMARY_CHUNK_CHARS: usize = 24_000;
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the following conversation as a concise TL;DR. \
+    Cover the main questions, answers and conclusions. Respond with only the summary.";
+
+/// Payload of the `session-summary` event streamed by `summarize_session`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SummaryChunk {
+    pub session_id: i64,
+    pub content: String,
+    pub done: bool,
+}
+
+// Split a transcript on message boundaries into chunks of roughly `max_chars`
+fn chunk_transcript(entries: &[String], max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for entry in entries {
+        if !current.is_empty() && current.len() + entry.len() > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(entry);
+        current.push_str("\n\n");
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+fn summary_request(model: &str, text: &str, stream: bool, keep_alive: Option<&serde_json::Value>) -> serde_json::Value {
+    let mut request_body = serde_json::json!({
+        "model": model,
+        "stream": stream,
+        "messages": [
+            { "role": "system", "content": SUMMARY_INSTRUCTION },
+            { "role": "user", "content": text }
+        ]
+    });
+    if let Some(keep_alive) = keep_alive {
+        request_body["keep_alive"] = keep_alive.clone();
+    }
+    request_body
+}
+
+// Summarize a whole session without modifying its history, streaming the summary as events
+pub async fn summarize_session(
+    session_id: i64,
+    model: String,
+    window: Window,
+    state: State<'_, Arc<Mutex<GenerationState>>>,
+    db_conn: State<'_, db::DbPool>,
+    limiter: State<'_, Arc<Mutex<RequestLimiter>>>,
+) -> Result<String, String> {
+    let keep_alive = resolve_keep_alive(None, db_conn.inner()).await?;
+    let history = db::fetch_chat_history(session_id, db_conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if history.is_empty() {
+        return Err("Session has no messages to summarize".to_string());
+    }
+
+    let entries: Vec<String> = history
+        .iter()
+        .map(|msg| format!("{}: {}", msg.role, strip_think(&msg.message)))
+        .collect();
+
+    let run = state
+        .lock()
+        .await
+        .begin(Some(session_id))
+        .ok_or_else(|| session::ALREADY_GENERATING.to_string())?;
+    let cancellation_token = run.token.clone();
+    let client = generation_client();
+
+    let result = tokio::select! {
+        result = async {
+            let _permit = session::acquire_permit(&limiter).await;
+
+            // Very long sessions are summarized piecewise, then the partial summaries are combined
+            let chunks = chunk_transcript(&entries, SUMMARY_CHUNK_CHARS);
+            let text = if chunks.len() > 1 {
+                let mut partials = Vec::new();
+                for chunk in &chunks {
+                    partials.push(chat_once(&client, &summary_request(&model, chunk, false, keep_alive.as_ref())).await?);
+                }
+                partials.join("\n\n")
+            } else {
+                chunks.concat()
+            };
+
+            stream_chat(&client, &summary_request(&model, &text, true, keep_alive.as_ref()), |content| {
+                let _ = window.emit("session-summary", SummaryChunk {
+                    session_id,
+                    content: content.to_string(),
+                    done: false,
+                });
+            })
+            .await
+        } => result,
+        _ = cancellation_token.cancelled() => Err("Summarization was cancelled".to_string()),
+    };
+
+    state.lock().await.finish(&run);
+
+    let summary = strip_think(&result?).trim().to_string();
+    let _ = window.emit("session-summary", SummaryChunk {
+        session_id,
+        content: String::new(),
+        done: true,
+    });
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_error_line_stops_with_partial_reply() {
+        let lines = [
+            r#"{"message":{"role":"assistant","content":"Hello"},"done":false}"#,
+            r#"{"message":{"role":"assistant","content":", wor"},"done":false}"#,
+            r#"{"error":"model runner has unexpectedly stopped"}"#,
+            r#"{"message":{"role":"assistant","content":"ld"},"done":false}"#,
+        ];
+
+        // Mirrors the loop in `stream_chat_generation`
+        let mut splitter = ThinkSplitter::default();
+        let mut reply = String::new();
+        let error = lines.iter().find_map(|line| match parse_stream_line(line) {
+            Ok(parsed) => {
+                if let Some(text) = parsed.and_then(|p| p.content) {
+                    reply.push_str(&splitter.push(&text).0);
+                }
+                None
+            }
+            Err(e) => Some(e),
+        });
+        reply.push_str(&splitter.finish().0);
+
+        assert!(matches!(error, Some(GenerationError::StreamError(ref m)) if m == "model runner has unexpectedly stopped"));
+        assert_eq!(reply, "Hello, wor");
+    }
+
+    #[test]
+    fn stream_done_line_reports_truncation() {
+        let parsed = parse_stream_line(r#"{"message":{"content":""},"done":true,"done_reason":"length","eval_count":3}"#)
+            .unwrap()
+            .unwrap();
+        assert!(parsed.done.is_some_and(|done| done.truncated));
+        assert!(parse_stream_line("not json").unwrap().is_none());
+    }
+
+    #[test]
+    fn error_body_names_missing_model() {
+        let status = reqwest::StatusCode::NOT_FOUND;
+        let message = body_error(status, r#"{"error":"model \"llama3:8b\" not found, try pulling it first"}"#);
+        assert!(message.contains("llama3:8b"), "{}", message);
+
+        assert_eq!(body_error(status, "  page not found\n"), "page not found");
+        assert_eq!(body_error(status, ""), "API call failed with status: 404 Not Found");
+    }
+
+    #[test]
+    fn stop_sequences_are_sent_in_options() {
+        let mut request_body = serde_json::json!({ "model": "llama3", "messages": [] });
+        let options = GenerationOptions {
+            stop: Some(vec!["\nUser:".to_string(), "###".to_string()]),
+            format: Some(serde_json::json!("json")),
+            ..Default::default()
+        };
+        apply_options(&mut request_body, &options);
+
+        assert_eq!(request_body["options"]["stop"], serde_json::json!(["\nUser:", "###"]));
+        assert_eq!(request_body["format"], "json");
+        assert!(request_body["options"].get("format").is_none());
+        assert!(request_body["options"].get("temperature").is_none());
+    }
+
+    #[test]
+    fn stopped_and_truncated_replies_are_saved() {
+        let conn = db::open_test_db();
+        let session_id = db::create_session(&conn, "Test").unwrap();
+
+        for (stopped, done_reason) in [(true, None), (false, Some("length"))] {
+            conn.execute(
+                "INSERT INTO chat_history (session_id, role, message, timestamp) VALUES (?1, 'assistant', '', '2024-05-01T12:30:00Z')",
+                [session_id],
+            )
+            .unwrap();
+            let message_id = conn.last_insert_rowid();
+            let done = ChatDonePayload {
+                session_id,
+                message_id,
+                content: "Partial ans".to_string(),
+                thinking: None,
+                stopped,
+                truncated: done_reason == Some("length"),
+                format_warning: None,
+                stats: None,
+                error: None,
+            };
+            save_reply(&conn, &done).unwrap();
+
+            let saved = db::fetch_chat_history_batch(&conn, session_id, message_id - 1, 1).unwrap().remove(0);
+            assert_eq!(saved.id, message_id);
+            assert_eq!(saved.message, "Partial ans");
+            assert_eq!((saved.stopped, saved.truncated), (done.stopped, done.truncated));
+
+            // The UI finalizes the row from the `chat-done` payload
+            let payload = serde_json::to_value(&done).unwrap();
+            assert_eq!(payload["content"], "Partial ans");
+            assert_eq!(payload["stopped"], stopped);
+            assert!(payload["error"].is_null());
+        }
+    }
+
+    #[test]
+    fn empty_reply_row_is_dropped() {
+        let conn = db::open_test_db();
+        let session_id = db::create_session(&conn, "Test").unwrap();
+        conn.execute(
+            "INSERT INTO chat_history (session_id, role, message, timestamp) VALUES (?1, 'assistant', '', '2024-05-01T12:30:00Z')",
+            [session_id],
+        )
+        .unwrap();
+        let done = ChatDonePayload {
+            session_id,
+            message_id: conn.last_insert_rowid(),
+            content: String::new(),
+            thinking: None,
+            stopped: true,
+            truncated: false,
+            format_warning: None,
+            stats: None,
+            error: None,
+        };
+        save_reply(&conn, &done).unwrap();
 
+        assert!(db::fetch_chat_history_batch(&conn, session_id, 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bare_model_name_matches_latest_tag() {
+        assert!(same_model("llama3", "llama3:latest"));
+        assert!(same_model("llama3:latest", "llama3"));
+        assert!(same_model("llama3:8b", "llama3:8b"));
+        assert!(!same_model("llama3", "llama3:8b"));
+    }
+}
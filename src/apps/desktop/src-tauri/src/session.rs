@@ -1,19 +1,204 @@
 // Manages AI generation state and session handling
 
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio_util::sync::CancellationToken;
 
 pub struct GenerationState {
     pub is_running: bool,
     pub current_session_id: Option<i64>,
     pub cancellation_token: Option<CancellationToken>,
+    /// Session the running generation belongs to; it can differ from `current_session_id`.
+    pub generating_session_id: Option<i64>,
+    /// Model used by the running chat generation.
+    pub model: Option<String>,
+    /// Id of the latest run handed out by `begin`.
+    run_id: u64,
 }
 
+/// A generation started by `GenerationState::begin`; hand it back to `finish` when it ends.
+#[derive(Clone)]
+pub struct GenerationRun {
+    pub token: CancellationToken,
+    id: u64,
+}
+
+/// Error for a generation started while another is running.
+pub const ALREADY_GENERATING: &str = "A generation is already running; stop it before starting another";
+
 impl Default for GenerationState {
     fn default() -> Self {
         Self {
             is_running: false,
             current_session_id: Some(-1),
             cancellation_token: None,
+            generating_session_id: None,
+            model: None,
+            run_id: 0,
+        }
+    }
+}
+
+/// Read-only view of `GenerationState` used for diagnostics.
+#[derive(Debug, serde::Serialize)]
+pub struct GenerationStateSnapshot {
+    pub is_running: bool,
+    pub current_session_id: Option<i64>,
+    pub has_cancellation_token: bool,
+    pub generating_session_id: Option<i64>,
+}
+
+impl GenerationState {
+    /// Marks a generation as running and returns the run holding the token that cancels it, or
+    /// `None` when another generation is already running; both would share this one token and session.
+    pub fn begin(&mut self, session_id: Option<i64>) -> Option<GenerationRun> {
+        if self.is_running {
+            return None;
+        }
+        let token = CancellationToken::new();
+        self.run_id += 1;
+        self.is_running = true;
+        self.cancellation_token = Some(token.clone());
+        self.generating_session_id = session_id;
+        Some(GenerationRun { token, id: self.run_id })
+    }
+
+    /// Marks `run` as finished. Does nothing if `run` was aborted and another generation
+    /// has begun since, so a late finish never clears the newer one.
+    pub fn finish(&mut self, run: &GenerationRun) {
+        if !self.is_running || run.id != self.run_id {
+            return;
+        }
+        self.is_running = false;
+        self.cancellation_token = None;
+        self.generating_session_id = None;
+        self.model = None;
+    }
+
+    /// Cancels the running generation, if any, and marks the state idle.
+    pub fn abort(&mut self) {
+        if let Some(token) = &self.cancellation_token {
+            token.cancel(); // Trigger cancellation
+        }
+
+        self.is_running = false; // Update state to indicate generation is no longer running
+        self.cancellation_token = None; // Clear the cancellation token
+        self.generating_session_id = None;
+        self.model = None;
+    }
+
+    /// Cancels the running generation only if it belongs to `session_id`.
+    /// Returns whether a generation was aborted; any other session is left alone.
+    pub fn abort_session(&mut self, session_id: i64) -> bool {
+        let generating = self.is_running && self.generating_session_id == Some(session_id);
+        if generating {
+            self.abort();
+        }
+        generating
+    }
+
+    /// Aborts the running generation of `session_id` if it uses a model other than `model`.
+    /// Returns whether a generation was aborted.
+    pub fn abort_if_model_changed(&mut self, session_id: i64, model: &str) -> bool {
+        let changed = self.is_running
+            && self.generating_session_id == Some(session_id)
+            && self.model.as_deref().is_some_and(|running| running != model);
+        if changed {
+            self.abort();
+        }
+        changed
+    }
+
+    pub fn snapshot(&self) -> GenerationStateSnapshot {
+        GenerationStateSnapshot {
+            is_running: self.is_running,
+            current_session_id: self.current_session_id,
+            has_cancellation_token: self.cancellation_token.is_some(),
+            generating_session_id: self.generating_session_id,
+        }
+    }
+}
+
+/// Cancellation tokens of the model downloads in progress, keyed by model name.
+#[derive(Default)]
+pub struct PullState {
+    pulls: HashMap<String, CancellationToken>,
+}
+
+impl PullState {
+    /// Registers a pull of `model` and returns the token that cancels it,
+    /// or `None` when that model is already being pulled.
+    pub fn begin(&mut self, model: &str) -> Option<CancellationToken> {
+        if self.pulls.contains_key(model) {
+            return None;
         }
+        let token = CancellationToken::new();
+        self.pulls.insert(model.to_string(), token.clone());
+        Some(token)
     }
+
+    /// Forgets the pull of `model` once it has ended.
+    pub fn finish(&mut self, model: &str) {
+        self.pulls.remove(model);
+    }
+
+    /// Cancels the pull of `model`. Returns whether a pull was running.
+    pub fn cancel(&mut self, model: &str) -> bool {
+        match self.pulls.remove(model) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Default number of requests allowed to hit Ollama at the same time.
+pub const DEFAULT_MAX_CONCURRENCY: usize = 1;
+
+/// Caps the number of concurrent Ollama requests; excess requests wait for a permit.
+pub struct RequestLimiter {
+    semaphore: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl RequestLimiter {
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Changes the permit count. Requests already holding a permit finish normally;
+    /// new requests queue against the new limit.
+    pub fn set_limit(&mut self, limit: usize) {
+        *self = Self::new(limit);
+    }
+
+    pub fn semaphore(&self) -> Arc<Semaphore> {
+        self.semaphore.clone()
+    }
+}
+
+impl Default for RequestLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENCY)
+    }
+}
+
+/// Waits until the limiter grants a permit; the permit is released when dropped.
+pub async fn acquire_permit(limiter: &tokio::sync::Mutex<RequestLimiter>) -> OwnedSemaphorePermit {
+    let semaphore = limiter.lock().await.semaphore();
+    semaphore
+        .acquire_owned()
+        .await
+        .expect("Request limiter semaphore closed")
 }
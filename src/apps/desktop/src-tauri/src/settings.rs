@@ -0,0 +1,238 @@
+// Loads and saves the settings screen's values as one object
+
+use crate::cache;
+use crate::db;
+use crate::embeddings;
+use crate::ollama_api::{self, GenerationOptions};
+use crate::shortcuts;
+use crate::tray;
+use crate::window_state;
+
+use rusqlite::Connection;
+
+/// Config keys the app reads. `set_config` only accepts these and `cleanup_config` removes the rest.
+pub const KNOWN_CONFIG_KEYS: &[&str] = &[
+    "selected_model_name",
+    "title_model",
+    "ollama_url",
+    "theme",
+    "default_options",
+    "keep_alive",
+    "show_thinking",
+    "cancel_on_model_change",
+    "debug_mode",
+    "max_concurrency",
+    CONNECT_TIMEOUT_KEY,
+    REQUEST_TIMEOUT_KEY,
+    MAX_RETRIES_KEY,
+    MODEL_LIST_TTL_KEY,
+    "window_x",
+    "window_y",
+    "window_width",
+    "window_height",
+    window_state::WINDOW_LAYOUTS_KEY,
+    shortcuts::ABORT_HOTKEY_KEY,
+    shortcuts::QUICK_CHAT_HOTKEY_KEY,
+    embeddings::EMBEDDING_MODEL_KEY,
+    CLEANUP_ON_STARTUP_KEY,
+    ATTACHMENT_MAX_BYTES_KEY,
+    TITLE_MODEL_PREFIX_KEY,
+    tray::MINIMIZE_TO_TRAY_KEY,
+    db::LAST_SESSION_KEY,
+];
+
+/// Seconds to wait for a connection to Ollama.
+pub const CONNECT_TIMEOUT_KEY: &str = "connect_timeout_secs";
+/// Seconds a non-streaming Ollama request may take.
+pub const REQUEST_TIMEOUT_KEY: &str = "request_timeout_secs";
+/// Times a request is retried when Ollama is unreachable or answers 5xx.
+pub const MAX_RETRIES_KEY: &str = "max_retries";
+/// Seconds the installed model list is cached; 0 fetches it every time.
+pub const MODEL_LIST_TTL_KEY: &str = "model_list_ttl_secs";
+
+/// When "true", unknown config keys are purged at startup.
+pub const CLEANUP_ON_STARTUP_KEY: &str = "cleanup_config_on_startup";
+
+/// When "true", generated session titles start with the model name, e.g. "llama3:8b: Topic".
+pub const TITLE_MODEL_PREFIX_KEY: &str = "title_model_prefix";
+
+/// Largest file, in bytes, that can be attached to a prompt.
+pub const ATTACHMENT_MAX_BYTES_KEY: &str = "attachment_max_bytes";
+pub const DEFAULT_ATTACHMENT_MAX_BYTES: u64 = 256 * 1024;
+
+/// Returns the attachment size limit, falling back to the default when unset or invalid.
+pub fn attachment_max_bytes(conn: &Connection) -> u64 {
+    db::get_config_value(conn, ATTACHMENT_MAX_BYTES_KEY)
+        .ok()
+        .flatten()
+        .and_then(|v| v.parse().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(DEFAULT_ATTACHMENT_MAX_BYTES)
+}
+
+/// Every setting shown on the settings screen.
+#[derive(Debug, serde::Serialize)]
+pub struct Settings {
+    pub ollama_url: String,
+    pub selected_model: Option<String>,
+    /// Model used to title new sessions; the chat model when unset.
+    pub title_model: Option<String>,
+    pub theme: Option<String>,
+    /// Options for sessions that don't set their own.
+    pub default_options: Option<GenerationOptions>,
+    pub keep_alive: Option<String>,
+    pub show_thinking: bool,
+    pub cancel_on_model_change: bool,
+    pub debug_mode: bool,
+    /// Closing the window hides it to the tray instead of quitting.
+    pub minimize_to_tray: bool,
+}
+
+/// Settings to change. Absent fields are left alone; an empty string clears a text setting.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct SettingsUpdate {
+    pub ollama_url: Option<String>,
+    pub selected_model: Option<String>,
+    pub title_model: Option<String>,
+    pub theme: Option<String>,
+    pub default_options: Option<GenerationOptions>,
+    pub keep_alive: Option<String>,
+    pub show_thinking: Option<bool>,
+    pub cancel_on_model_change: Option<bool>,
+    pub debug_mode: Option<bool>,
+    pub minimize_to_tray: Option<bool>,
+}
+
+/// Applies the settings that live outside the database (Ollama URL, HTTP timeouts, retries, model list TTL).
+pub fn apply_runtime_config(conn: &Connection) {
+    let value = |key: &str| db::get_config_value(conn, key).ok().flatten();
+    ollama_api::set_ollama_url(value("ollama_url").as_deref());
+    ollama_api::set_timeouts(
+        value(CONNECT_TIMEOUT_KEY).and_then(|v| v.parse().ok()),
+        value(REQUEST_TIMEOUT_KEY).and_then(|v| v.parse().ok()),
+    );
+    ollama_api::set_max_retries(value(MAX_RETRIES_KEY).and_then(|v| v.parse().ok()));
+    cache::set_model_list_ttl(value(MODEL_LIST_TTL_KEY).and_then(|v| v.parse().ok()));
+}
+
+fn text(conn: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
+    Ok(db::get_config_value(conn, key)?.filter(|v| !v.is_empty()))
+}
+
+fn flag(conn: &Connection, key: &str, default: bool) -> rusqlite::Result<bool> {
+    Ok(db::get_config_value(conn, key)?.map(|v| v == "true").unwrap_or(default))
+}
+
+pub fn load_settings(conn: &Connection) -> rusqlite::Result<Settings> {
+    Ok(Settings {
+        ollama_url: text(conn, "ollama_url")?.unwrap_or_else(|| ollama_api::DEFAULT_OLLAMA_URL.to_string()),
+        selected_model: text(conn, "selected_model_name")?,
+        title_model: text(conn, "title_model")?,
+        theme: text(conn, "theme")?,
+        default_options: db::get_default_options(conn),
+        keep_alive: text(conn, "keep_alive")?,
+        show_thinking: flag(conn, "show_thinking", true)?,
+        cancel_on_model_change: flag(conn, "cancel_on_model_change", false)?,
+        debug_mode: flag(conn, "debug_mode", false)?,
+        minimize_to_tray: flag(conn, tray::MINIMIZE_TO_TRAY_KEY, false)?,
+    })
+}
+
+// Store a text setting, deleting the key when the value is empty
+fn save_text(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
+    match value.trim() {
+        "" => db::delete_config_value(conn, key),
+        value => db::update_config_value(conn, key, value),
+    }
+}
+
+fn write_settings(conn: &Connection, update: &SettingsUpdate) -> rusqlite::Result<()> {
+    let texts = [
+        ("ollama_url", &update.ollama_url),
+        ("selected_model_name", &update.selected_model),
+        ("title_model", &update.title_model),
+        ("theme", &update.theme),
+        ("keep_alive", &update.keep_alive),
+    ];
+    for (key, value) in texts {
+        if let Some(value) = value {
+            save_text(conn, key, value)?;
+        }
+    }
+
+    let flags = [
+        ("show_thinking", update.show_thinking),
+        ("cancel_on_model_change", update.cancel_on_model_change),
+        ("debug_mode", update.debug_mode),
+        (tray::MINIMIZE_TO_TRAY_KEY, update.minimize_to_tray),
+    ];
+    for (key, value) in flags {
+        if let Some(value) = value {
+            db::update_config_value(conn, key, if value { "true" } else { "false" })?;
+        }
+    }
+
+    if let Some(options) = &update.default_options {
+        match db::options_to_json(&Some(options.clone())) {
+            Some(json) => db::update_config_value(conn, "default_options", &json)?,
+            None => db::delete_config_value(conn, "default_options")?,
+        }
+    }
+    Ok(())
+}
+
+/// Validates and applies `update` in a single transaction, returning the resulting settings.
+pub fn update_settings(conn: &mut Connection, update: SettingsUpdate) -> Result<Settings, String> {
+    if let Some(url) = update.ollama_url.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("Invalid Ollama URL '{}': expected http:// or https://", url));
+        }
+    }
+    if let Some(keep_alive) = update.keep_alive.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        ollama_api::parse_keep_alive(keep_alive)?;
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    write_settings(&tx, &update).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    apply_runtime_config(conn);
+
+    load_settings(conn).map_err(|e| e.to_string())
+}
+
+/// Sets a known config key, or deletes it when `value` is `None` or empty.
+pub fn set_config(conn: &Connection, key: &str, value: Option<&str>) -> Result<(), String> {
+    if !KNOWN_CONFIG_KEYS.contains(&key) {
+        return Err(format!("Unknown config key '{}'", key));
+    }
+    let value = value.map(str::trim).unwrap_or_default();
+    if key == "keep_alive" && !value.is_empty() {
+        ollama_api::parse_keep_alive(value)?;
+    }
+    if (key == CONNECT_TIMEOUT_KEY || key == REQUEST_TIMEOUT_KEY) && !value.is_empty() && value.parse::<u64>().is_err() {
+        return Err(format!("Invalid value '{}' for {}: expected whole seconds", value, key));
+    }
+    if (key == MAX_RETRIES_KEY || key == MODEL_LIST_TTL_KEY) && !value.is_empty() && value.parse::<u64>().is_err() {
+        return Err(format!("Invalid value '{}' for {}: expected a whole number", value, key));
+    }
+    if key == ATTACHMENT_MAX_BYTES_KEY && !value.is_empty() && !value.parse::<u64>().is_ok_and(|bytes| bytes > 0) {
+        return Err(format!("Invalid value '{}' for {}: expected a positive number of bytes", value, key));
+    }
+    save_text(conn, key, value).map_err(|e| e.to_string())?;
+    apply_runtime_config(conn);
+    Ok(())
+}
+
+/// Removes config keys that aren't in `KNOWN_CONFIG_KEYS`, returning the removed keys.
+/// `schema_version` is bookkeeping rather than a setting, so it is always kept.
+pub fn cleanup_config(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let stale: Vec<String> = db::fetch_config_keys(conn)?
+        .into_iter()
+        .filter(|key| key != "schema_version" && !KNOWN_CONFIG_KEYS.contains(&key.as_str()))
+        .collect();
+    for key in &stale {
+        db::delete_config_value(conn, key)?;
+    }
+    Ok(stale)
+}
@@ -0,0 +1,122 @@
+// Registers global keyboard shortcuts
+
+use crate::db;
+use crate::session::GenerationState;
+use rusqlite::Connection;
+use std::sync::Arc;
+use tauri::{AppHandle, GlobalShortcutManager, Manager, WindowBuilder, WindowUrl};
+use tokio::sync::Mutex;
+
+/// Config key holding the accelerator that aborts the running generation.
+pub const ABORT_HOTKEY_KEY: &str = "abort_hotkey";
+
+/// Config key holding the accelerator that opens the quick-chat window.
+pub const QUICK_CHAT_HOTKEY_KEY: &str = "quick_chat_hotkey";
+pub const DEFAULT_QUICK_CHAT_HOTKEY: &str = "CmdOrCtrl+Shift+Space";
+
+const QUICK_CHAT_LABEL: &str = "quick_chat";
+
+// Register `keys` to run `handler`, replacing `previous` only once the new one is in place
+fn register_hotkey<F>(app: &AppHandle, previous: Option<&str>, keys: &str, handler: F) -> Result<(), String>
+where
+    F: Fn() + Send + 'static,
+{
+    if previous == Some(keys) {
+        return Ok(());
+    }
+
+    let mut manager = app.global_shortcut_manager();
+
+    if manager.is_registered(keys).map_err(|e| format!("Invalid shortcut {}: {}", keys, e))? {
+        return Err(format!("Shortcut {} is already in use", keys));
+    }
+
+    manager
+        .register(keys, handler)
+        .map_err(|e| format!("Failed to register shortcut {}: {}", keys, e))?;
+
+    if let Some(previous) = previous {
+        unregister(app, previous);
+    }
+
+    Ok(())
+}
+
+// Register `keys` as the abort hotkey, replacing `previous` only once the new one is in place
+pub fn register_abort_hotkey(app: &AppHandle, previous: Option<&str>, keys: &str) -> Result<(), String> {
+    let handle = app.clone();
+    register_hotkey(app, previous, keys, move || {
+        let state = handle.state::<Arc<Mutex<GenerationState>>>().inner().clone();
+        tauri::async_runtime::spawn(async move {
+            state.lock().await.abort();
+        });
+    })
+}
+
+// Register `keys` as the quick-chat hotkey, replacing `previous` only once the new one is in place
+pub fn register_quick_chat_hotkey(app: &AppHandle, previous: Option<&str>, keys: &str) -> Result<(), String> {
+    let handle = app.clone();
+    register_hotkey(app, previous, keys, move || {
+        let handle = handle.clone();
+        // Building a window from the shortcut callback can deadlock on Windows, so do it off that thread
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = open_quick_chat_window(&handle) {
+                eprintln!("Failed to open the quick-chat window: {}", e);
+            }
+        });
+    })
+}
+
+// Focus the small always-on-top prompt window, creating it on first use
+fn open_quick_chat_window(app: &AppHandle) -> tauri::Result<()> {
+    if let Some(window) = app.get_window(QUICK_CHAT_LABEL) {
+        window.show()?;
+        return window.set_focus();
+    }
+
+    WindowBuilder::new(app, QUICK_CHAT_LABEL, WindowUrl::App("index.html?window=quick-chat".into()))
+        .title("Quick Chat")
+        .inner_size(640.0, 140.0)
+        .resizable(false)
+        .decorations(false)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .center()
+        .focused(true)
+        .build()?;
+    Ok(())
+}
+
+/// Returns the quick-chat accelerator saved in config, or the default.
+pub fn quick_chat_hotkey(conn: &Connection) -> rusqlite::Result<String> {
+    Ok(db::get_config_value(conn, QUICK_CHAT_HOTKEY_KEY)?
+        .filter(|keys| !keys.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_QUICK_CHAT_HOTKEY.to_string()))
+}
+
+pub fn unregister(app: &AppHandle, keys: &str) {
+    if let Err(e) = app.global_shortcut_manager().unregister(keys) {
+        eprintln!("Failed to unregister shortcut {}: {}", keys, e);
+    }
+}
+
+// Register the abort hotkey saved in config, if any, and the quick-chat hotkey
+pub fn register_saved_shortcuts(app: &AppHandle, conn: &Connection) {
+    match db::get_config_value(conn, ABORT_HOTKEY_KEY) {
+        Ok(Some(keys)) => {
+            if let Err(e) = register_abort_hotkey(app, None, &keys) {
+                eprintln!("{}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to read abort hotkey: {}", e),
+    }
+    match quick_chat_hotkey(conn) {
+        Ok(keys) => {
+            if let Err(e) = register_quick_chat_hotkey(app, None, &keys) {
+                eprintln!("{}", e);
+            }
+        }
+        Err(e) => eprintln!("Failed to read quick-chat hotkey: {}", e),
+    }
+}
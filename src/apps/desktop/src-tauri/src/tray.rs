@@ -0,0 +1,80 @@
+// System tray icon, its quick actions and the close-to-tray behaviour
+
+use crate::db;
+use crate::session::GenerationState;
+use rusqlite::Connection;
+use std::sync::Arc;
+use tauri::{
+    AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, Window,
+};
+use tokio::sync::Mutex;
+
+/// When "true", closing the main window hides it to the tray instead of quitting.
+pub const MINIMIZE_TO_TRAY_KEY: &str = "minimize_to_tray";
+
+pub fn system_tray() -> SystemTray {
+    let menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("show", "Show"))
+        .add_item(CustomMenuItem::new("new_chat", "New Chat"))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("quit", "Quit"));
+    SystemTray::new().with_menu(menu)
+}
+
+pub fn minimize_to_tray(conn: &Connection) -> bool {
+    db::get_config_value(conn, MINIMIZE_TO_TRAY_KEY)
+        .ok()
+        .flatten()
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+fn show_main_window(app: &AppHandle) -> Option<Window> {
+    let window = app.get_window("main")?;
+    let _ = window.show();
+    let _ = window.unminimize();
+    let _ = window.set_focus();
+    Some(window)
+}
+
+fn toggle_main_window(app: &AppHandle) {
+    match app.get_window("main") {
+        Some(window) if window.is_visible().unwrap_or(false) => {
+            let _ = window.hide();
+        }
+        _ => {
+            show_main_window(app);
+        }
+    }
+}
+
+// Leave the current session and ask the frontend to open an empty chat
+fn start_new_chat(app: &AppHandle) {
+    let state = app.state::<Arc<Mutex<GenerationState>>>().inner().clone();
+    let db_conn = app.state::<db::DbPool>().inner().clone();
+    let window = show_main_window(app);
+    tauri::async_runtime::spawn(async move {
+        state.lock().await.current_session_id = Some(-1);
+        if let Err(e) = db::checkout(&db_conn).and_then(|conn| db::set_last_session(&conn, None)) {
+            eprintln!("Failed to forget the last session: {}", e);
+        }
+        if let Some(window) = window {
+            if let Err(e) = window.emit("new-chat-requested", ()) {
+                eprintln!("Failed to emit new-chat-requested event: {}", e);
+            }
+        }
+    });
+}
+
+pub fn handle_tray_event(app: &AppHandle, event: SystemTrayEvent) {
+    match event {
+        SystemTrayEvent::LeftClick { .. } => toggle_main_window(app),
+        SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
+            "show" => toggle_main_window(app),
+            "new_chat" => start_new_chat(app),
+            "quit" => app.exit(0),
+            _ => {}
+        },
+        _ => {}
+    }
+}
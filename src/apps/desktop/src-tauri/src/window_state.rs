@@ -0,0 +1,161 @@
+// Persists and restores the main window geometry
+
+use crate::db;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tauri::{Monitor, PhysicalPosition, PhysicalSize};
+
+pub const DEFAULT_WIDTH: u32 = 1600;
+pub const DEFAULT_HEIGHT: u32 = 1440;
+
+/// Config key holding a JSON object of window geometry keyed by monitor layout.
+pub const WINDOW_LAYOUTS_KEY: &str = "window_layouts";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Geometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+// Identify the connected monitors, e.g. "DP-1:2560x1440@0,0|eDP-1:1920x1200@2560,0"
+fn layout_key(monitors: &[Monitor]) -> Option<String> {
+    if monitors.is_empty() {
+        return None;
+    }
+    let mut parts: Vec<String> = monitors
+        .iter()
+        .map(|m| {
+            format!(
+                "{}:{}x{}@{},{}",
+                m.name().map(String::as_str).unwrap_or("?"),
+                m.size().width,
+                m.size().height,
+                m.position().x,
+                m.position().y
+            )
+        })
+        .collect();
+    parts.sort();
+    Some(parts.join("|"))
+}
+
+fn load_layouts(conn: &Connection) -> rusqlite::Result<HashMap<String, Geometry>> {
+    Ok(db::get_config_value(conn, WINDOW_LAYOUTS_KEY)?
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default())
+}
+
+// Pixels of `geometry` that fall on `monitor`
+fn overlap(geometry: &Geometry, monitor: &Monitor) -> i64 {
+    let (mx, my) = (monitor.position().x as i64, monitor.position().y as i64);
+    let (mw, mh) = (monitor.size().width as i64, monitor.size().height as i64);
+    let w = (geometry.x as i64 + geometry.width as i64).min(mx + mw) - (geometry.x as i64).max(mx);
+    let h = (geometry.y as i64 + geometry.height as i64).min(my + mh) - (geometry.y as i64).max(my);
+    w.max(0) * h.max(0)
+}
+
+// Move and shrink `geometry` so it lies entirely on `monitor`
+fn clamp_to(geometry: Geometry, monitor: &Monitor) -> Geometry {
+    let width = geometry.width.min(monitor.size().width);
+    let height = geometry.height.min(monitor.size().height);
+    let max_x = monitor.position().x + (monitor.size().width - width) as i32;
+    let max_y = monitor.position().y + (monitor.size().height - height) as i32;
+    Geometry {
+        x: geometry.x.clamp(monitor.position().x, max_x),
+        y: geometry.y.clamp(monitor.position().y, max_y),
+        width,
+        height,
+    }
+}
+
+// Save window state
+pub fn save_window_state(window: &tauri::Window, conn: &Connection) -> rusqlite::Result<()> {
+    let (Ok(position), Ok(size)) = (window.outer_position(), window.outer_size()) else {
+        return Ok(());
+    };
+
+    db::update_config_value(conn, "window_x", &position.x.to_string())?;
+    db::update_config_value(conn, "window_y", &position.y.to_string())?;
+    db::update_config_value(conn, "window_width", &size.width.to_string())?;
+    db::update_config_value(conn, "window_height", &size.height.to_string())?;
+
+    // Also remember it for the current monitor layout so docking and undocking each restore their own spot
+    let monitors = window.available_monitors().unwrap_or_default();
+    if let Some(layout) = layout_key(&monitors) {
+        let mut layouts = load_layouts(conn)?;
+        layouts.insert(layout, Geometry { x: position.x, y: position.y, width: size.width, height: size.height });
+        let json = serde_json::to_string(&layouts).expect("window layouts always serialize");
+        db::update_config_value(conn, WINDOW_LAYOUTS_KEY, &json)?;
+    }
+
+    Ok(())
+}
+
+// Load window state
+pub fn load_window_state(window: &tauri::Window, conn: &Connection) -> rusqlite::Result<()> {
+    let monitors = window.available_monitors().unwrap_or_default();
+    let saved = match layout_key(&monitors) {
+        Some(layout) => load_layouts(conn)?.remove(&layout),
+        None => None,
+    };
+
+    let geometry = match saved {
+        Some(geometry) => geometry,
+        None => {
+            let x = db::get_config_value(conn, "window_x")?.unwrap_or_else(|| "100".to_string()).parse().unwrap_or(100);
+            let y = db::get_config_value(conn, "window_y")?.unwrap_or_else(|| "100".to_string()).parse().unwrap_or(100);
+            let width = db::get_config_value(conn, "window_width")?.unwrap_or_else(|| DEFAULT_WIDTH.to_string()).parse().unwrap_or(800);
+            let height = db::get_config_value(conn, "window_height")?.unwrap_or_else(|| DEFAULT_HEIGHT.to_string()).parse().unwrap_or(600);
+            Geometry { x, y, width, height }
+        }
+    };
+
+    // Pull the window onto the monitor it overlaps most; if it is on none of them, center it instead
+    let geometry = match monitors.iter().max_by_key(|m| overlap(&geometry, m)) {
+        Some(monitor) if overlap(&geometry, monitor) > 0 => clamp_to(geometry, monitor),
+        Some(_) => {
+            if let Err(e) = center_on_primary(window) {
+                eprintln!("Failed to center the window: {}", e);
+            }
+            return Ok(());
+        }
+        None => geometry,
+    };
+
+    window.set_position(tauri::Position::Physical(PhysicalPosition::new(geometry.x, geometry.y)))
+        .expect("Failed to set window position");
+
+    window.set_size(PhysicalSize::new(geometry.width, geometry.height))
+        .expect("Failed to set window size");
+
+    Ok(())
+}
+
+// Give the window the default size, centered on the primary monitor
+fn center_on_primary(window: &tauri::Window) -> Result<(), String> {
+    let monitor = window
+        .primary_monitor()
+        .map_err(|e| e.to_string())?
+        .or(window.current_monitor().map_err(|e| e.to_string())?)
+        .ok_or_else(|| "No monitor available".to_string())?;
+
+    // Never make the default window larger than the monitor it is placed on
+    let width = DEFAULT_WIDTH.min(monitor.size().width);
+    let height = DEFAULT_HEIGHT.min(monitor.size().height);
+    let x = monitor.position().x + (monitor.size().width - width) as i32 / 2;
+    let y = monitor.position().y + (monitor.size().height - height) as i32 / 2;
+
+    window.set_size(PhysicalSize::new(width, height))
+        .map_err(|e| e.to_string())?;
+    window.set_position(tauri::Position::Physical(PhysicalPosition::new(x, y)))
+        .map_err(|e| e.to_string())
+}
+
+// Reset the window to the default size, centered on the primary monitor, and save it
+pub fn reset_window_geometry(window: &tauri::Window, conn: &Connection) -> Result<(), String> {
+    center_on_primary(window)?;
+    save_window_state(window, conn).map_err(|e| e.to_string())
+}
@@ -1,5 +1,6 @@
 import React, { useState, useEffect, useRef, useImperativeHandle, forwardRef } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
+import { listen } from "@tauri-apps/api/event";
 import ReactMarkdown from "react-markdown";
 
 const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, onNewSession }, ref) => {
@@ -8,7 +9,9 @@ const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, o
     const [userInput, setUserInput] = useState("");
     const [chatHistory, setChatHistory] = useState([]);
     const [loading, setLoading] = useState(false);
+    const [streamingResponse, setStreamingResponse] = useState("");
     const chatEndRef = useRef(null);
+    const currentSessionRef = useRef(currentSessionId);
     const [windowHeight, setWindowHeight] = useState(window.innerHeight);
 
     // Load models and chat history on startup
@@ -48,10 +51,29 @@ const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, o
         }
     }, [currentSessionId]);
 
+    useEffect(() => {
+        currentSessionRef.current = currentSessionId;
+    }, [currentSessionId]);
+
+    // Show the response as it streams; tokens from another session's stream are ignored
+    useEffect(() => {
+        const unlisten = listen("chat-token", (event) => {
+            const current = currentSessionRef.current;
+            if (current !== null && current !== -1 && event.payload.session_id !== current) {
+                return;
+            }
+            setStreamingResponse((prev) => prev + event.payload.content);
+        });
+
+        return () => {
+            unlisten.then((stop) => stop());
+        };
+    }, []);
+
     // Auto-scroll on new message
     useEffect(() => {
         scrollToBottom();
-    }, [chatHistory]);
+    }, [chatHistory, streamingResponse]);
 
     // Adjust height on window resize
     useEffect(() => {
@@ -84,9 +106,10 @@ const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, o
             try {
                 const savedModel = await invoke("get_selected_model");
 
-                // If savedModel is null or invalid, default to the first model in the models list
+                // If savedModel is null or invalid, use the suggested default or the first model in the list
                 if (!savedModel || savedModel.trim() === "") {
-                    const defaultModel = models[0];
+                    const suggestedModel = await invoke("suggest_default_model").catch(() => null);
+                    const defaultModel = suggestedModel || models[0];
                     setSelectedModel(defaultModel);
                     saveModelSelection(defaultModel);
                 } else {
@@ -153,6 +176,7 @@ const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, o
         }
 
         const emptyChatHistory = (chatHistory.length === 0);
+        setStreamingResponse("");
         setLoading(true);
 
         try {
@@ -190,6 +214,7 @@ const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, o
             alert("Failed to generate a response.");
         } finally {
             setLoading(false);
+            setStreamingResponse("");
         }
     };
 
@@ -252,6 +277,16 @@ const ChatWindow = forwardRef(({ className, currentSessionId, refreshSessions, o
                         </div>
                     ))
                 )}
+
+                {/* Response being generated */}
+                {loading && streamingResponse && (
+                    <div className="bg-green-100 self-start text-left mr-auto border-green-500 p-4 rounded-lg border-l-4">
+                        <strong className="text-gray-800 font-bold">🤖 AI:</strong>
+                        <div className="prose">
+                            <ReactMarkdown>{streamingResponse}</ReactMarkdown>
+                        </div>
+                    </div>
+                )}
             </div>
 
             <div ref={chatEndRef} />